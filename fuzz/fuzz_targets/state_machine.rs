@@ -0,0 +1,8 @@
+#![no_main]
+
+use contig_storage::fuzzing::Scenario;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|scenario: Scenario| {
+    scenario.run();
+});
@@ -0,0 +1,209 @@
+// A `ContigStorage` of fixed capacity `N` that never allocates: the slots and the
+// indirect-only flags live inline, so it can be built in a `const` or `static`.
+//
+// The slots are managed by the same code as those of `ContigStorage`, see `slots.rs`,
+// with `KeyEncoding::Xor`, no instance tagging and `GrowBehavior::None`. The flags are a
+// `[bool; N]`: packing them into words would need the word count as a second parameter.
+
+use crate::slots::{Slots, SlotsMut};
+use crate::{ContigError, Corruption, Item, Key, KeyRng, Keylike, Stats};
+use core::fmt::{self, Debug};
+use core::marker::PhantomData;
+use core::num::NonZeroUsize;
+
+/// Clones keep the slot layout and key scrambling of the original, as those of
+/// `ContigStorage` do.
+///
+/// Removing always moves the last value into the hole, as `RemovePolicy::SwapRemove`
+/// does: keeping the order takes a key table, which lives on the heap.
+#[derive(Clone)]
+pub struct ArrayContigStorage<T: Copy, const N: usize, K: Keylike = Key> {
+    data: [Item<T>; N],
+    len: usize,
+    start_of_clean: usize,
+    indirection_xor: usize,
+    indirect_only: [bool; N],
+    key_rng: KeyRng,
+    _key: PhantomData<K>,
+}
+
+impl<T, const N: usize> ArrayContigStorage<T, N>
+where
+    T: Copy,
+{
+    /// Keys are scrambled with a stream seeded by `seed`, as for
+    /// `ContigStorage::new_with_seed`.
+    pub const fn new(seed: u64) -> Self {
+        Self::with_key_and_seed(seed)
+    }
+}
+impl<T, const N: usize, K> ArrayContigStorage<T, N, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// Like `new`, handing out keys of type `K`.
+    pub const fn with_key_and_seed(seed: u64) -> Self {
+        let mut key_rng = KeyRng(seed);
+        let indirection_xor = key_rng.next_u64() as usize;
+        Self {
+            data: [Item::NOTHING_ITEM; N],
+            len: 0,
+            start_of_clean: 0,
+            indirection_xor,
+            indirect_only: [false; N],
+            key_rng,
+            _key: PhantomData,
+        }
+    }
+
+    fn slots(&self) -> Slots<'_, T, [bool; N]> {
+        Slots {
+            data: &self.data,
+            len: self.len,
+            start_of_clean: self.start_of_clean,
+            flags: &self.indirect_only,
+        }
+    }
+    fn slots_mut(&mut self) -> SlotsMut<'_, T, [bool; N]> {
+        SlotsMut {
+            data: &mut self.data,
+            len: &mut self.len,
+            start_of_clean: &mut self.start_of_clean,
+            flags: &mut self.indirect_only,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn capacity(&self) -> usize {
+        N
+    }
+    pub fn invalidate_keys(&mut self) {
+        self.indirection_xor = self.key_rng.next_u64() as usize;
+    }
+    pub fn clear(&mut self) {
+        self.slots_mut().clear();
+        self.invalidate_keys();
+        self.check_invariants();
+    }
+
+    /// See `ContigStorage::validate`. Allocates a bit per slot.
+    pub fn validate(&self) -> Result<(), Corruption> {
+        self.slots().validate()
+    }
+    /// See `ContigStorage::stats`. Allocates a word per slot past `len`.
+    pub fn stats(&self) -> Stats {
+        self.slots().stats()
+    }
+    // See `ContigStorage::check_invariants`.
+    fn check_invariants(&self) {
+        #[cfg(feature = "debug-invariants")]
+        {
+            if let Err(corruption) = self.validate() {
+                panic!("ArrayContigStorage corrupted: {}", corruption);
+            }
+        }
+    }
+
+    // See `ContigStorage::index_to_key`.
+    fn index_to_key(&self, index: usize) -> K {
+        let mut payload = (index + 1) ^ self.indirection_xor;
+        if payload == 0 {
+            payload ^= self.indirection_xor;
+        }
+        K::key_wrap(NonZeroUsize::new(payload).expect("key payload is never zero"))
+    }
+    fn key_to_index(&self, key: K) -> usize {
+        let mut x = key.key_unwrap().get() ^ self.indirection_xor;
+        if x == 0 {
+            x ^= self.indirection_xor;
+        }
+        x - 1
+    }
+
+    /// Fails with `ContigError::Full` once `N` values are stored.
+    pub fn add(&mut self, value: T) -> Result<K, ContigError> {
+        if self.len >= N {
+            return Err(ContigError::Full);
+        }
+        let result = self.slots_mut().add(value).map(|index| self.index_to_key(index));
+        self.check_invariants();
+        result
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<T> {
+        self.try_remove(key).ok()
+    }
+    pub fn try_remove(&mut self, key: K) -> Result<T, ContigError> {
+        let index = self.key_to_index(key);
+        self.slots().resolve_key_index(index)?;
+        let value = self.slots_mut().remove_index(index).ok_or(ContigError::Corrupted);
+        self.check_invariants();
+        value
+    }
+
+    pub fn get(&self, key: K) -> Option<&T> {
+        self.try_get(key).ok()
+    }
+    pub fn try_get(&self, key: K) -> Result<&T, ContigError> {
+        let slots = self.slots();
+        let index = slots.resolve_key_index(self.key_to_index(key))?;
+        Ok(slots.value(index))
+    }
+    pub fn get_mut(&mut self, key: K) -> Option<&mut T> {
+        self.try_get_mut(key).ok()
+    }
+    pub fn try_get_mut(&mut self, key: K) -> Result<&mut T, ContigError> {
+        let index = self.slots().resolve_key_index(self.key_to_index(key))?;
+        Ok(self.slots_mut().into_value_mut(index))
+    }
+
+    /// Panics if `T` is smaller than a `usize`, as `ContigStorage::get_slice` does.
+    pub fn get_slice(&self) -> &[T] {
+        self.slots().values()
+    }
+    pub fn get_slice_mut(&mut self) -> &mut [T] {
+        self.slots_mut().into_values_mut()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let slots = self.slots();
+        (0..self.len).map(move |i| slots.value(i))
+    }
+}
+
+impl<T, const N: usize, K> Debug for ArrayContigStorage<T, N, K>
+where
+    T: Copy + Debug,
+    K: Keylike,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.slots().fmt(f)?;
+        f.write_fmt(format_args!(", xor: {:X}\n", self.indirection_xor))
+    }
+}
+impl<T, const N: usize, K> core::ops::Index<K> for ArrayContigStorage<T, N, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    type Output = T;
+    fn index(&self, key: K) -> &T {
+        self.get(key)
+            .expect("ArrayContigStorage indexed with invalid key.")
+    }
+}
+impl<T, const N: usize, K> core::ops::IndexMut<K> for ArrayContigStorage<T, N, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    fn index_mut(&mut self, key: K) -> &mut T {
+        self.get_mut(key)
+            .expect("ArrayContigStorage indexed with invalid key.")
+    }
+}
@@ -0,0 +1,166 @@
+// A `ContigStorage` whose dense region is split into buckets, each a contiguous run of
+// slots, in bucket order. Values are moved between runs by swapping them across the
+// boundaries in between, one swap per boundary, with the key table keeping every key
+// bound to its value. Within a bucket, the order of values is unspecified.
+
+use crate::{ContigError, ContigStorage, GrowBehavior, Iter, Key, Keylike};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+pub struct BucketedContigStorage<T: Copy, K: Keylike = Key> {
+    storage: ContigStorage<T, K>,
+    // the first slot of each bucket. The last bucket ends at `len`.
+    starts: Vec<usize>,
+}
+
+impl<T> BucketedContigStorage<T>
+where
+    T: Copy,
+{
+    pub fn new(capacity: usize, grow_behavior: GrowBehavior) -> Self {
+        Self::from_storage(ContigStorage::new(capacity, grow_behavior))
+    }
+}
+impl<T, K> BucketedContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// Takes over `storage`, putting all its values in bucket 0. Its keys remain valid,
+    /// and all its values become active.
+    pub fn from_storage(mut storage: ContigStorage<T, K>) -> Self {
+        storage.active_len = storage.len();
+        // otherwise an add may move an existing value to the end
+        storage.track();
+        let starts = if storage.is_empty() { Vec::new() } else { vec![0] };
+        Self { storage, starts }
+    }
+    /// Read access to the underlying storage.
+    pub fn storage(&self) -> &ContigStorage<T, K> {
+        &self.storage
+    }
+    pub fn into_inner(self) -> ContigStorage<T, K> {
+        self.storage
+    }
+
+    /// Buckets are numbered from 0 and come into being as values are added to them.
+    pub fn bucket_count(&self) -> usize {
+        self.starts.len()
+    }
+    fn end(&self, bucket: usize) -> usize {
+        self.starts.get(bucket + 1).copied().unwrap_or(self.storage.len())
+    }
+    fn bucket_of_slot(&self, slot: usize) -> usize {
+        // empty buckets share their start with the next one, so take the last match
+        self.starts.partition_point(|&start| start <= slot) - 1
+    }
+    // Moves the value in `slot`, part of bucket `from`, to bucket `to`, returning its new
+    // slot. Takes one swap per bucket boundary crossed.
+    fn relocate(&mut self, mut slot: usize, from: usize, to: usize) -> usize {
+        let mut bucket = from;
+        while bucket < to {
+            let last = self.end(bucket) - 1;
+            self.storage.swap_slots(slot, last);
+            self.starts[bucket + 1] -= 1;
+            slot = last;
+            bucket += 1;
+        }
+        while bucket > to {
+            let first = self.starts[bucket];
+            self.storage.swap_slots(slot, first);
+            self.starts[bucket] += 1;
+            slot = first;
+            bucket -= 1;
+        }
+        slot
+    }
+
+    /// O(b) in the number of buckets after `bucket`.
+    pub fn add(&mut self, bucket: usize, value: T) -> Result<K, ContigError> {
+        let key = self.storage.add(value)?;
+        let slot = self.storage.len() - 1;
+        if bucket >= self.starts.len() {
+            self.starts.resize(bucket + 1, slot);
+        }
+        let last_bucket = self.starts.len() - 1;
+        self.relocate(slot, last_bucket, bucket);
+        self.storage.check_invariants();
+        Ok(key)
+    }
+    pub fn remove(&mut self, key: K) -> Option<T> {
+        self.try_remove(key).ok()
+    }
+    /// O(b) in the number of buckets after the value's.
+    pub fn try_remove(&mut self, key: K) -> Result<T, ContigError> {
+        let slot = self.storage.resolve_key_index(self.storage.key_to_index(key)?)?;
+        let last_bucket = self.starts.len() - 1;
+        let slot = self.relocate(slot, self.bucket_of_slot(slot), last_bucket);
+        self.storage.swap_slots(slot, self.storage.len() - 1);
+        self.storage.try_remove(key)
+    }
+    /// Moves the value of `key` to `bucket`, keeping its key. O(b) in the number of
+    /// buckets between the two.
+    pub fn move_to_bucket(&mut self, key: K, bucket: usize) -> Result<(), ContigError> {
+        let slot = self.storage.resolve_key_index(self.storage.key_to_index(key)?)?;
+        if bucket >= self.starts.len() {
+            self.starts.resize(bucket + 1, self.storage.len());
+        }
+        self.relocate(slot, self.bucket_of_slot(slot), bucket);
+        self.storage.check_invariants();
+        Ok(())
+    }
+    pub fn bucket_of(&self, key: K) -> Option<usize> {
+        Some(self.bucket_of_slot(self.storage.get_slice_index(key)?))
+    }
+
+    pub fn get(&self, key: K) -> Option<&T> {
+        self.storage.get(key)
+    }
+    pub fn try_get(&self, key: K) -> Result<&T, ContigError> {
+        self.storage.try_get(key)
+    }
+    pub fn get_mut(&mut self, key: K) -> Option<&mut T> {
+        self.storage.get_mut(key)
+    }
+    pub fn try_get_mut(&mut self, key: K) -> Result<&mut T, ContigError> {
+        self.storage.try_get_mut(key)
+    }
+
+    /// The slots of `bucket` within `get_slice`. Empty for buckets never added to.
+    pub fn bucket_range(&self, bucket: usize) -> Range<usize> {
+        match self.starts.get(bucket) {
+            Some(&start) => start..self.end(bucket),
+            None => self.storage.len()..self.storage.len(),
+        }
+    }
+    /// Panics if `T` is smaller than a `usize`, as `ContigStorage::get_slice` does.
+    pub fn bucket_slice(&self, bucket: usize) -> &[T] {
+        &self.storage.get_slice()[self.bucket_range(bucket)]
+    }
+    pub fn bucket_slice_mut(&mut self, bucket: usize) -> &mut [T] {
+        let range = self.bucket_range(bucket);
+        &mut self.storage.get_slice_mut()[range]
+    }
+    /// All buckets, in bucket order.
+    pub fn get_slice(&self) -> &[T] {
+        self.storage.get_slice()
+    }
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.storage.iter()
+    }
+    /// Empties every bucket. Bucket numbers stay in use.
+    pub fn clear(&mut self) {
+        self.storage.clear();
+        self.storage.track();
+        for start in self.starts.iter_mut() {
+            *start = 0;
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+}
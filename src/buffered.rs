@@ -0,0 +1,268 @@
+// A `ContigStorage` whose dense region is published to readers in frames. Writers
+// mutate the back buffer through the usual keys; `swap` makes its current contents
+// visible as a `Frame`, which is unaffected by writes until the next `swap`. A frame
+// also maps the keys of its values to their slots, so readers can look values up by key
+// as of that frame. With more than two buffers, the published frames rotate, and each is
+// only patched with the slots that changed since it was last published.
+//
+// The back buffer keeps a key table, see `tracked.rs`, so that the key owning a changed
+// slot is found in O(1) when patching.
+
+use crate::{ContigError, ContigStorage, GrowBehavior, Key, Keylike, RemovePolicy};
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use bit_vec::BitVec;
+use core::marker::PhantomData;
+
+// Slots of the back buffer's dense region written since a front was last refreshed.
+#[derive(Default)]
+struct Dirty {
+    slots: Vec<usize>,
+    // keeps `slots` free of duplicates
+    bits: BitVec,
+}
+
+/// The dense region of a `BufferedContigStorage` as of some `swap`, along with the slots
+/// of its keys. Besides the values, takes a word per slot and a map entry per key.
+pub struct Frame<T: Copy, K: Keylike = Key> {
+    values: Vec<T>,
+    // per slot: the raw key of its value
+    owners: Vec<usize>,
+    // raw key -> slot
+    slots: BTreeMap<usize, usize>,
+    _key: PhantomData<K>,
+}
+
+pub struct BufferedContigStorage<T: Copy, K: Keylike = Key> {
+    back: ContigStorage<T, K>,
+    fronts: Vec<Frame<T, K>>,
+    dirty: Vec<Dirty>,
+    current: usize,
+}
+
+/// Mutable access to the back buffer of a `BufferedContigStorage`.
+pub struct BackBuffer<'a, T: Copy, K: Keylike = Key> {
+    back: &'a mut ContigStorage<T, K>,
+    dirty: &'a mut [Dirty],
+}
+
+impl<T, K> Frame<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// The value `key` had when this frame was published.
+    pub fn get(&self, key: K) -> Option<&T> {
+        Some(&self.values[self.get_slice_index(key)?])
+    }
+    pub fn get_slice_index(&self, key: K) -> Option<usize> {
+        self.slots.get(&key.key_unwrap().get()).copied()
+    }
+    pub fn get_slice(&self) -> &[T] {
+        &self.values
+    }
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    fn bind(&mut self, slot: usize, key: K) {
+        let raw = key.key_unwrap().get();
+        self.owners[slot] = raw;
+        self.slots.insert(raw, slot);
+    }
+    // Forgets the key of `slot`, unless it has been bound to another slot since.
+    fn unbind(&mut self, slot: usize) {
+        let raw = self.owners[slot];
+        if self.slots.get(&raw) == Some(&slot) {
+            self.slots.remove(&raw);
+        }
+    }
+}
+impl<T, K> Clone for Frame<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    fn clone(&self) -> Self {
+        Self {
+            values: self.values.clone(),
+            owners: self.owners.clone(),
+            slots: self.slots.clone(),
+            _key: PhantomData,
+        }
+    }
+}
+
+impl<T> BufferedContigStorage<T>
+where
+    T: Copy,
+{
+    /// `buffers` counts the back buffer too: 2 for double buffering, 3 for triple.
+    pub fn new(capacity: usize, grow_behavior: GrowBehavior, buffers: usize) -> Self {
+        Self::from_storage(ContigStorage::new(capacity, grow_behavior), buffers)
+    }
+}
+impl<T, K> BufferedContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// Uses `storage` as the back buffer. Its contents are published right away and
+    /// its keys remain valid.
+    pub fn from_storage(mut storage: ContigStorage<T, K>, buffers: usize) -> Self {
+        assert!(buffers >= 2, "BufferedContigStorage needs at least 2 buffers");
+        storage.track();
+        let mut published = Frame {
+            values: storage.iter().copied().collect(),
+            owners: vec![0; storage.len()],
+            slots: BTreeMap::new(),
+            _key: PhantomData,
+        };
+        for (slot, (key, _)) in storage.iter_with_keys().enumerate() {
+            published.bind(slot, key);
+        }
+        Self {
+            back: storage,
+            fronts: vec![published; buffers - 1],
+            dirty: (1..buffers).map(|_| Dirty::default()).collect(),
+            current: 0,
+        }
+    }
+
+    /// The frame published by the last `swap`.
+    pub fn front(&self) -> &Frame<T, K> {
+        &self.fronts[self.current]
+    }
+    /// The dense region as of the last `swap`.
+    pub fn get_slice(&self) -> &[T] {
+        self.front().get_slice()
+    }
+    /// The value of `key` as of the last `swap`.
+    pub fn get(&self, key: K) -> Option<&T> {
+        self.front().get(key)
+    }
+    /// Read access to the back buffer, including all writes since the last `swap`.
+    pub fn back(&self) -> &ContigStorage<T, K> {
+        &self.back
+    }
+    pub fn back_mut(&mut self) -> BackBuffer<'_, T, K> {
+        BackBuffer {
+            back: &mut self.back,
+            dirty: &mut self.dirty,
+        }
+    }
+    /// Borrows the published frame and the back buffer at once, so that one frame can
+    /// be read while the next is written.
+    pub fn split(&mut self) -> (&Frame<T, K>, BackBuffer<'_, T, K>) {
+        let back = BackBuffer {
+            back: &mut self.back,
+            dirty: &mut self.dirty,
+        };
+        (&self.fronts[self.current], back)
+    }
+    /// Publishes the back buffer. Only the slots written since the now current front
+    /// was last published are copied and have their keys rebound.
+    pub fn swap(&mut self) {
+        // `clear` drops the key table
+        self.back.track();
+        self.current = (self.current + 1) % self.fronts.len();
+        let front = &mut self.fronts[self.current];
+        let dirty = &mut self.dirty[self.current];
+        let back = &self.back;
+        let key_of = &back.tracked.as_ref().unwrap().key_of;
+        let len = back.len();
+        for slot in len..front.len() {
+            front.unbind(slot);
+        }
+        front.values.truncate(len);
+        front.owners.truncate(len);
+        for (slot, &index) in key_of.iter().enumerate().skip(front.len()) {
+            front.values.push(back.copy_value(slot));
+            front.owners.push(0);
+            front.bind(slot, back.index_to_key(index));
+        }
+        for &slot in dirty.slots.iter() {
+            if slot < front.len() {
+                front.values[slot] = back.copy_value(slot);
+                front.unbind(slot);
+                front.bind(slot, back.index_to_key(key_of[slot]));
+            }
+            dirty.bits.set(slot, false);
+        }
+        dirty.slots.clear();
+    }
+
+    pub fn into_inner(self) -> ContigStorage<T, K> {
+        self.back
+    }
+}
+
+impl<'a, T, K> BackBuffer<'a, T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    fn mark_dirty(&mut self, slot: usize) {
+        for dirty in self.dirty.iter_mut() {
+            if slot >= dirty.bits.len() {
+                dirty.bits.grow(slot + 1 - dirty.bits.len(), false);
+            }
+            if !dirty.bits.get(slot).unwrap() {
+                dirty.bits.set(slot, true);
+                dirty.slots.push(slot);
+            }
+        }
+    }
+
+    pub fn get(&self, key: K) -> Option<&T> {
+        self.back.get(key)
+    }
+    pub fn get_mut(&mut self, key: K) -> Option<&mut T> {
+        let slot = self.back.get_slice_index(key)?;
+        self.mark_dirty(slot);
+        self.back.get_mut(key)
+    }
+    pub fn add(&mut self, value: T) -> Result<K, ContigError> {
+        // `clear` drops the key table, and with it new values always go at the end
+        self.back.track();
+        let key = self.back.add(value)?;
+        // with inactive values, the first inactive one moved to the end to make room
+        self.mark_dirty(self.back.len() - 1);
+        self.mark_dirty(self.back.get_slice_index(key).unwrap());
+        Ok(key)
+    }
+    pub fn remove(&mut self, key: K) -> Option<T> {
+        let slot = self.back.get_slice_index(key)?;
+        let value = self.back.remove(key)?;
+        match self.back.remove_policy {
+            // the value at the boundary moved into the hole, by way of the last active
+            // slot if there are inactive values
+            RemovePolicy::SwapRemove => {
+                self.mark_dirty(slot);
+                if self.back.active_len() < self.back.len() {
+                    self.mark_dirty(self.back.active_len());
+                }
+            }
+            // every later value moved down by one
+            RemovePolicy::ShiftPreserveOrder => {
+                for slot in slot..self.back.len() {
+                    self.mark_dirty(slot);
+                }
+            }
+        }
+        Some(value)
+    }
+    pub fn clear(&mut self) {
+        self.back.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.back.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.back.is_empty()
+    }
+}
@@ -0,0 +1,46 @@
+// Byte views of the dense region for `T: Pod`, e.g. for uploading to the GPU.
+
+use crate::{ContigStorage, Item, Keylike};
+use bytemuck::Pod;
+use core::mem::size_of;
+use core::ops::Range;
+
+/// Only for `T` at least as big as a `usize`, such as `u64`, `[f32; 2]` or a vertex
+/// struct. Smaller `T` (`u32`, `f32`, `[u16; 3]`, ...) has its slots padded to hold
+/// indirections, so there are no contiguous bytes to view, and using these methods with
+/// it is a compile error:
+///
+/// ```compile_fail
+/// use contig_storage::{ContigStorage, GrowBehavior};
+/// let storage = ContigStorage::<f32>::new(8, GrowBehavior::None);
+/// storage.as_bytes(); // error: f32 is smaller than a usize
+/// ```
+impl<T, K> ContigStorage<T, K>
+where
+    T: Pod,
+    K: Keylike,
+{
+    const BYTES_ARE_CONTIGUOUS: () = assert!(
+        size_of::<Item<T>>() <= size_of::<T>(),
+        "byte views need T to be at least as big as a usize"
+    );
+
+    /// The bytes of `get_slice()`.
+    pub fn as_bytes(&self) -> &[u8] {
+        let () = Self::BYTES_ARE_CONTIGUOUS;
+        bytemuck::cast_slice(self.get_slice())
+    }
+    /// The bytes of `get_slice_mut()`.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        let () = Self::BYTES_ARE_CONTIGUOUS;
+        bytemuck::cast_slice_mut(self.get_slice_mut())
+    }
+    /// Copies the bytes of the dense region into `dst`, starting at byte `offset`.
+    /// Returns the range of `dst` written, or `None` (writing nothing) if it doesn't fit.
+    pub fn copy_bytes_into(&self, dst: &mut [u8], offset: usize) -> Option<Range<usize>> {
+        let src = self.as_bytes();
+        let range = offset..offset.checked_add(src.len())?;
+        dst.get_mut(range.clone())?.copy_from_slice(src);
+        Some(range)
+    }
+}
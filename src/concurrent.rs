@@ -0,0 +1,184 @@
+// A `ContigStorage` shared between threads, using the left-right technique
+// (Ramalhete & Correia): two copies of the storage are kept, readers only ever enter the
+// copy that no writer is touching, and every write is applied to both copies in turn.
+// Readers never block and never take a lock; writers are serialised by a mutex and wait
+// for readers to leave a copy before touching it.
+//
+// Both sides announce themselves (reader: read indicator, writer: `left_right`) and then
+// look at what the other side announced. A SeqCst fence between the two steps guarantees
+// that at least one of them sees the other.
+
+use crate::sync::{fence, yield_now, AtomicUsize, ConstPtr, Mutex, Ordering, UnsafeCell};
+use crate::{ContigError, ContigStorage, GrowBehavior, Key, Keylike};
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+
+pub struct ConcurrentContigStorage<T: Copy, K: Keylike = Key> {
+    copies: [UnsafeCell<ContigStorage<T, K>>; 2],
+    // which copy newly arriving readers enter
+    left_right: AtomicUsize,
+    // which of `read_indicators` newly arriving readers announce themselves in
+    version_index: AtomicUsize,
+    read_indicators: [AtomicUsize; 2],
+    writer: Mutex<()>,
+}
+
+// Readers on several threads share the copies, and writers mutate them from whichever
+// thread calls `write`.
+unsafe impl<T, K> Sync for ConcurrentContigStorage<T, K>
+where
+    T: Copy + Send + Sync,
+    K: Keylike + Send + Sync,
+{
+}
+unsafe impl<T, K> Send for ConcurrentContigStorage<T, K>
+where
+    T: Copy + Send,
+    K: Keylike + Send,
+{
+}
+
+/// Shared access to a consistent version of a `ConcurrentContigStorage`. Writers wait for
+/// it to be dropped before touching the version it shows, so don't hold it across a write
+/// on the same thread.
+pub struct ReadGuard<'a, T: Copy, K: Keylike = Key> {
+    storage: &'a ConcurrentContigStorage<T, K>,
+    version: usize,
+    // released before the read indicator, see `drop`
+    copy: ManuallyDrop<ConstPtr<ContigStorage<T, K>>>,
+}
+impl<'a, T, K> Deref for ReadGuard<'a, T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    type Target = ContigStorage<T, K>;
+    fn deref(&self) -> &Self::Target {
+        // The read indicator announced in `read` keeps writers out of this copy until
+        // the guard is dropped.
+        unsafe { (*self.copy).deref() }
+    }
+}
+impl<'a, T, K> Drop for ReadGuard<'a, T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    fn drop(&mut self) {
+        // Under loom the pointer records an access until dropped, which must end before
+        // writers are let back into the copy.
+        unsafe { ManuallyDrop::drop(&mut self.copy) };
+        self.storage.read_indicators[self.version].fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<T> ConcurrentContigStorage<T>
+where
+    T: Copy,
+{
+    pub fn new(capacity: usize, grow_behavior: GrowBehavior) -> Self {
+        Self::from(ContigStorage::new(capacity, grow_behavior))
+    }
+}
+impl<T, K> From<ContigStorage<T, K>> for ConcurrentContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// Keys issued by `storage` remain valid.
+    fn from(storage: ContigStorage<T, K>) -> Self {
+        Self {
+            copies: [UnsafeCell::new(storage.clone()), UnsafeCell::new(storage)],
+            left_right: AtomicUsize::new(0),
+            version_index: AtomicUsize::new(0),
+            read_indicators: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writer: Mutex::new(()),
+        }
+    }
+}
+impl<T, K> ConcurrentContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// Returns a view of the latest completed version of the storage without blocking.
+    pub fn read(&self) -> ReadGuard<'_, T, K> {
+        let version = self.version_index.load(Ordering::SeqCst);
+        self.read_indicators[version].fetch_add(1, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        let lr = self.left_right.load(Ordering::SeqCst);
+        ReadGuard {
+            storage: self,
+            version,
+            copy: ManuallyDrop::new(self.copies[lr].get()),
+        }
+    }
+    pub fn get(&self, key: K) -> Option<T> {
+        self.read().get(key).copied()
+    }
+    /// Copies out the dense region of one consistent version.
+    pub fn snapshot(&self) -> Vec<T> {
+        self.read().get_slice().to_vec()
+    }
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    /// Applies `op` to the storage, serialised with other writers. Readers see either
+    /// none or all of its effects. `op` is run twice, once on each copy, and must behave
+    /// the same both times: it is handed identical storages, so any closure that only
+    /// depends on its argument and on values it captured qualifies. The result of the
+    /// first run is returned. If `op` panics, the storage stays readable but later
+    /// writes panic.
+    pub fn write<F, R>(&self, op: F) -> R
+    where
+        F: Fn(&mut ContigStorage<T, K>) -> R,
+    {
+        let _writer = self.writer.lock().expect("ConcurrentContigStorage poisoned");
+        let lr = self.left_right.load(Ordering::SeqCst);
+        // Readers only enter copy `lr`; the previous write drained copy `1 - lr`.
+        let result = self.copies[1 - lr].with_mut(|copy| op(unsafe { &mut *copy }));
+        self.left_right.store(1 - lr, Ordering::SeqCst);
+        self.wait_for_readers();
+        // Nobody can be reading copy `lr` anymore.
+        self.copies[lr].with_mut(|copy| op(unsafe { &mut *copy }));
+        result
+    }
+    // Waits until every reader that may have seen the old `left_right` value is gone.
+    // Readers that announce themselves after `version_index` flips necessarily load the
+    // new `left_right`, so it is enough to drain both indicators one after another.
+    fn wait_for_readers(&self) {
+        let prev = self.version_index.load(Ordering::SeqCst);
+        let next = 1 - prev;
+        fence(Ordering::SeqCst);
+        while self.read_indicators[next].load(Ordering::SeqCst) != 0 {
+            yield_now();
+        }
+        self.version_index.store(next, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        while self.read_indicators[prev].load(Ordering::SeqCst) != 0 {
+            yield_now();
+        }
+    }
+
+    pub fn add(&self, value: T) -> Result<K, ContigError> {
+        self.write(|s| s.add(value))
+    }
+    pub fn remove(&self, key: K) -> Option<T> {
+        self.write(|s| s.remove(key))
+    }
+    /// Overwrites the value of `key`, returning whether it was present.
+    pub fn set(&self, key: K, value: T) -> bool {
+        self.write(|s| s.get_mut(key).map(|v| *v = value).is_some())
+    }
+    pub fn clear(&self) {
+        self.write(|s| s.clear())
+    }
+    pub fn into_inner(self) -> ContigStorage<T, K> {
+        let [_, copy] = self.copies;
+        copy.into_inner()
+    }
+}
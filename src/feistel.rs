@@ -0,0 +1,43 @@
+// Keyed permutation of the low `bits` bits of a `usize`, used by `KeyEncoding::Keyed`.
+//
+// A balanced Feistel network over the two halves of those bits, with SipHash-1-3 as
+// the round function. Four rounds of a pseudorandom round function give a strong
+// pseudorandom permutation (Luby-Rackoff), so seeing any number of keys says nothing
+// useful about the key of a slot that was never handed out.
+
+use siphasher::sip::SipHasher13;
+use core::hash::Hasher;
+
+const ROUNDS: u8 = 4;
+
+fn round(secret: &[u64; 2], round: u8, half: usize, half_mask: usize) -> usize {
+    let mut hasher = SipHasher13::new_with_keys(secret[0], secret[1]);
+    hasher.write_u8(round);
+    hasher.write_u64(half as u64);
+    hasher.finish() as usize & half_mask
+}
+
+// `bits` must be even and at most `usize::BITS`. Bits of `x` above `bits` are ignored.
+pub(crate) fn encrypt(secret: &[u64; 2], bits: u32, x: usize) -> usize {
+    let half_bits = bits / 2;
+    let half_mask = usize::MAX >> (usize::BITS - half_bits);
+    let (mut l, mut r) = ((x >> half_bits) & half_mask, x & half_mask);
+    for i in 0..ROUNDS {
+        let next = l ^ round(secret, i, r, half_mask);
+        l = r;
+        r = next;
+    }
+    (l << half_bits) | r
+}
+
+pub(crate) fn decrypt(secret: &[u64; 2], bits: u32, x: usize) -> usize {
+    let half_bits = bits / 2;
+    let half_mask = usize::MAX >> (usize::BITS - half_bits);
+    let (mut l, mut r) = ((x >> half_bits) & half_mask, x & half_mask);
+    for i in (0..ROUNDS).rev() {
+        let prev = r ^ round(secret, i, l, half_mask);
+        r = l;
+        l = prev;
+    }
+    (l << half_bits) | r
+}
@@ -0,0 +1,267 @@
+// Drives a `ContigStorage` through an arbitrary sequence of operations and checks it
+// against a `HashMap` model after every step, along with `validate`. Used by the
+// cargo-fuzz targets in `fuzz/` and by the randomized tests.
+//
+// Values are `u64` so that `get_slice` is available. Keys are only guaranteed to be
+// rejected until the next reroll, so stale keys are probed within the key epoch they
+// were issued in.
+
+use crate::{ContigError, ContigStorage, GrowBehavior, Key, KeyEncoding, RemovePolicy};
+use alloc::vec;
+use alloc::vec::Vec;
+use arbitrary::Arbitrary;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Arbitrary, Debug, Clone)]
+pub enum Op {
+    Add(u64),
+    /// Removes the live key at this position (modulo the number of live keys).
+    Remove(u8),
+    GetMut(u8, u64),
+    /// Looks up a key removed earlier in the current key epoch.
+    ProbeStale(u8),
+    Clear,
+    InvalidateKeys,
+    AssignNewKeys,
+    /// Takes at most this many values from `drain`. Exhausting it clears the storage.
+    Drain(u8),
+    /// Switches to `RemovePolicy::ShiftPreserveOrder` if true, else to `SwapRemove`.
+    ShiftOnRemove(bool),
+    /// Sorts by the value modulo 4, stably if true.
+    Sort(bool),
+    /// Swaps the values of two live keys.
+    Swap(u8, u8),
+    /// Moves the value of a live key to this slot (modulo `len`).
+    MoveToIndex(u8, u8),
+    Deactivate(u8),
+    Activate(u8),
+}
+
+#[derive(Arbitrary, Debug, Clone)]
+pub struct Scenario {
+    pub capacity: u8,
+    pub grow: bool,
+    pub keyed: bool,
+    pub tagged: bool,
+    pub seed: u64,
+    pub ops: Vec<Op>,
+}
+
+struct Model {
+    storage: ContigStorage<u64>,
+    live: HashMap<Key, u64>,
+    // removed in the current key epoch and not issued again since
+    stale: Vec<Key>,
+    inactive: HashSet<Key>,
+}
+
+impl Scenario {
+    /// Panics on the first divergence from the model.
+    pub fn run(&self) {
+        let grow_behavior = if self.grow {
+            GrowBehavior::Doubling
+        } else {
+            GrowBehavior::None
+        };
+        let mut storage = ContigStorage::new_with_seed(self.capacity as usize, grow_behavior, self.seed);
+        if self.keyed {
+            storage.set_key_encoding(KeyEncoding::Keyed);
+        }
+        storage.set_instance_tagging(self.tagged);
+        let mut model = Model {
+            storage,
+            live: HashMap::new(),
+            stale: vec![],
+            inactive: HashSet::new(),
+        };
+        for op in self.ops.iter() {
+            model.apply(op);
+            model.check();
+        }
+    }
+}
+
+impl Model {
+    fn nth_live(&self, n: u8) -> Option<Key> {
+        let mut keys: Vec<Key> = self.live.keys().copied().collect();
+        keys.sort();
+        keys.get(n as usize % keys.len().max(1)).copied()
+    }
+    fn new_epoch(&mut self) {
+        self.stale.clear();
+        self.live = self.storage.iter_with_keys().map(|(k, &v)| (k, v)).collect();
+        self.inactive = self.storage.iter_with_keys().skip(self.storage.active_len()).map(|(k, _)| k).collect();
+    }
+    // Checks that the storage holds `entries` in this order, with the values past the
+    // active prefix inactive from now on.
+    fn expect_layout(&self, entries: Vec<(Key, u64)>) {
+        assert!(self.storage.iter_with_keys().map(|(k, &v)| (k, v)).eq(entries.iter().copied()));
+    }
+
+    fn apply(&mut self, op: &Op) {
+        match *op {
+            Op::Add(value) => match self.storage.add(value) {
+                Ok(key) => {
+                    if self.shifting() {
+                        assert_eq!(self.storage.get_active_slice().last(), Some(&value));
+                    }
+                    assert!(self.live.insert(key, value).is_none(), "{:?} issued twice", key);
+                    self.stale.retain(|&k| k != key);
+                }
+                Err(ContigError::Full) => {
+                    let grows = self.storage.grow_behavior == GrowBehavior::Doubling
+                        && self.storage.capacity() < self.storage.max_capacity();
+                    assert!(!grows && self.storage.len() == self.storage.capacity());
+                }
+                Err(e) => panic!("add failed: {}", e),
+            },
+            Op::Remove(n) => {
+                if let Some(key) = self.nth_live(n) {
+                    let mut slice = self.storage.get_slice().to_vec();
+                    let slot = self.storage.get_slice_index(key).unwrap();
+                    assert_eq!(self.storage.try_remove(key), Ok(self.live.remove(&key).unwrap()));
+                    self.inactive.remove(&key);
+                    assert!(self.storage.get(key).is_none());
+                    if self.shifting() {
+                        slice.remove(slot);
+                        assert_eq!(self.storage.get_slice(), &slice[..]);
+                    }
+                    self.stale.push(key);
+                }
+            }
+            Op::GetMut(n, value) => {
+                if let Some(key) = self.nth_live(n) {
+                    *self.storage.try_get_mut(key).unwrap() = value;
+                    self.live.insert(key, value);
+                }
+            }
+            Op::ProbeStale(n) => {
+                if !self.stale.is_empty() {
+                    let key = self.stale[n as usize % self.stale.len()];
+                    assert!(self.storage.try_get(key).is_err(), "stale {:?} resolved", key);
+                    assert!(self.storage.try_get_mut(key).is_err());
+                    assert!(self.storage.try_remove(key).is_err());
+                }
+            }
+            Op::Clear => {
+                self.storage.clear();
+                self.new_epoch();
+            }
+            Op::InvalidateKeys => {
+                let before = self.values();
+                self.storage.invalidate_keys();
+                self.new_epoch();
+                assert_eq!(before, self.values());
+            }
+            Op::AssignNewKeys => {
+                let before = self.values();
+                let keys: Vec<Key> = self.storage.assign_new_keys().collect();
+                self.stale.clear();
+                self.inactive = keys[self.storage.active_len()..].iter().copied().collect();
+                self.live = keys.into_iter().zip(self.storage.iter().copied()).collect();
+                assert_eq!(before, self.values());
+            }
+            Op::Drain(n) => {
+                let slice = self.storage.get_slice().to_vec();
+                let drained: Vec<u64> = self.storage.drain().take(n as usize).collect();
+                assert_eq!(drained[..], slice[..drained.len()]);
+                if n as usize > slice.len() {
+                    assert!(self.storage.is_empty());
+                    self.new_epoch();
+                } else {
+                    assert_eq!(self.storage.get_slice(), &slice[..]);
+                }
+            }
+            Op::Sort(stable) => {
+                let mut slice = self.storage.get_slice().to_vec();
+                let (active, inactive) = slice.split_at_mut(self.storage.active_len());
+                active.sort_by_key(|v| v % 4);
+                inactive.sort_by_key(|v| v % 4);
+                if stable {
+                    self.storage.sort_by_key(|v| v % 4);
+                    assert_eq!(self.storage.get_slice(), &slice[..]);
+                } else {
+                    self.storage.sort_unstable_by(|a, b| (a % 4).cmp(&(b % 4)));
+                    let sorted = |part: &[u64]| part.windows(2).all(|w| w[0] % 4 <= w[1] % 4);
+                    let (active, inactive) = self.storage.get_slice().split_at(self.storage.active_len());
+                    assert!(sorted(active) && sorted(inactive));
+                }
+            }
+            Op::Swap(m, n) => {
+                if let (Some(a), Some(b)) = (self.nth_live(m), self.nth_live(n)) {
+                    let mut entries: Vec<(Key, u64)> = self.storage.iter_with_keys().map(|(k, &v)| (k, v)).collect();
+                    let (i, j) = (self.storage.get_slice_index(a).unwrap(), self.storage.get_slice_index(b).unwrap());
+                    if self.inactive.contains(&a) != self.inactive.contains(&b) {
+                        assert_eq!(self.storage.swap(a, b), Err(ContigError::CrossesActivePartition));
+                    } else {
+                        self.storage.swap(a, b).unwrap();
+                        entries.swap(i, j);
+                    }
+                    self.expect_layout(entries);
+                }
+            }
+            Op::MoveToIndex(n, index) => {
+                if let Some(key) = self.nth_live(n) {
+                    let mut entries: Vec<(Key, u64)> = self.storage.iter_with_keys().map(|(k, &v)| (k, v)).collect();
+                    // one past the end, to check that it's refused
+                    let index = index as usize % (entries.len() + 1);
+                    if index == entries.len() {
+                        assert_eq!(self.storage.move_to_index(key, index), Err(ContigError::OutOfBounds));
+                        return;
+                    }
+                    let active_len = self.storage.active_len();
+                    if self.inactive.contains(&key) != (index >= active_len) {
+                        assert_eq!(self.storage.move_to_index(key, index), Err(ContigError::CrossesActivePartition));
+                        return;
+                    }
+                    let entry = entries.remove(self.storage.get_slice_index(key).unwrap());
+                    entries.insert(index, entry);
+                    self.storage.move_to_index(key, index).unwrap();
+                    self.expect_layout(entries);
+                }
+            }
+            Op::Deactivate(n) => {
+                if let Some(key) = self.nth_live(n) {
+                    self.storage.deactivate(key).unwrap();
+                    self.inactive.insert(key);
+                }
+            }
+            Op::Activate(n) => {
+                if let Some(key) = self.nth_live(n) {
+                    self.storage.activate(key).unwrap();
+                    self.inactive.remove(&key);
+                }
+            }
+            Op::ShiftOnRemove(shift) => {
+                self.storage.remove_policy = if shift {
+                    RemovePolicy::ShiftPreserveOrder
+                } else {
+                    RemovePolicy::SwapRemove
+                };
+            }
+        }
+    }
+
+    fn shifting(&self) -> bool {
+        self.storage.remove_policy == RemovePolicy::ShiftPreserveOrder
+    }
+
+    fn values(&self) -> Vec<u64> {
+        let mut values: Vec<u64> = self.live.values().copied().collect();
+        values.sort();
+        values
+    }
+    fn check(&self) {
+        if let Err(corruption) = self.storage.validate() {
+            panic!("corrupted: {}", corruption);
+        }
+        let mut slice = self.storage.get_slice().to_vec();
+        slice.sort();
+        assert_eq!(slice, self.values());
+        for (&key, value) in self.live.iter() {
+            assert_eq!(self.storage.try_get(key), Ok(value));
+            assert_eq!(self.storage.is_active(key), Ok(!self.inactive.contains(&key)));
+        }
+        assert_eq!(self.storage.active_len(), self.live.len() - self.inactive.len());
+    }
+}
@@ -0,0 +1,248 @@
+// Iterators over the dense region, and the std collection traits built on them.
+
+use crate::{ContigError, ContigStorage, GrowBehavior, Item, Key, Keylike};
+use alloc::vec::Vec;
+use core::iter::{FromIterator, FusedIterator};
+use core::slice;
+
+/// Iterates over the values of a `ContigStorage` in slice order.
+pub struct Iter<'a, T: Copy>(pub(crate) slice::Iter<'a, Item<T>>);
+/// Iterates mutably over the values of a `ContigStorage` in slice order.
+pub struct IterMut<'a, T: Copy>(pub(crate) slice::IterMut<'a, Item<T>>);
+
+// SAFETY (all `unsafe` below): both are only ever built from the slots below `len`,
+// which hold values.
+impl<'a, T: Copy> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        self.0.next().map(|item| unsafe { &item.value })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+impl<'a, T: Copy> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        self.0.next_back().map(|item| unsafe { &item.value })
+    }
+}
+impl<'a, T: Copy> ExactSizeIterator for Iter<'a, T> {}
+impl<'a, T: Copy> FusedIterator for Iter<'a, T> {}
+
+impl<'a, T: Copy> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<&'a mut T> {
+        self.0.next().map(|item| unsafe { &mut item.value })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+impl<'a, T: Copy> DoubleEndedIterator for IterMut<'a, T> {
+    fn next_back(&mut self) -> Option<&'a mut T> {
+        self.0.next_back().map(|item| unsafe { &mut item.value })
+    }
+}
+impl<'a, T: Copy> ExactSizeIterator for IterMut<'a, T> {}
+impl<'a, T: Copy> FusedIterator for IterMut<'a, T> {}
+
+/// Copies the values out of a `ContigStorage` in slice order. Once exhausted from
+/// either end, the storage is cleared. Dropping it early leaves the storage untouched.
+pub struct ContigDrain<'a, T, K = Key>
+where
+    T: Copy,
+    K: Keylike,
+{
+    pub(crate) storage: &'a mut ContigStorage<T, K>,
+    pub(crate) front: usize,
+    pub(crate) back: usize,
+}
+impl<'a, T, K> Iterator for ContigDrain<'a, T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    type Item = T;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            self.storage.clear();
+            None
+        } else {
+            self.front += 1;
+            Some(self.storage.copy_value(self.front - 1))
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.back - self.front, Some(self.back - self.front))
+    }
+}
+impl<'a, T, K> DoubleEndedIterator for ContigDrain<'a, T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            self.storage.clear();
+            None
+        } else {
+            self.back -= 1;
+            Some(self.storage.copy_value(self.back))
+        }
+    }
+}
+impl<'a, T, K> ExactSizeIterator for ContigDrain<'a, T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+}
+
+/// Moves the values out of a `ContigStorage` in slice order.
+pub struct IntoIter<T, K = Key>
+where
+    T: Copy,
+    K: Keylike,
+{
+    storage: ContigStorage<T, K>,
+    front: usize,
+    back: usize,
+}
+impl<T, K> Iterator for IntoIter<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        if self.front == self.back {
+            return None;
+        }
+        self.front += 1;
+        Some(self.storage.copy_value(self.front - 1))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.back - self.front, Some(self.back - self.front))
+    }
+}
+impl<T, K> DoubleEndedIterator for IntoIter<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    fn next_back(&mut self) -> Option<T> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        Some(self.storage.copy_value(self.back))
+    }
+}
+impl<T, K> ExactSizeIterator for IntoIter<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+}
+impl<T, K> FusedIterator for IntoIter<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+}
+
+impl<T, K> IntoIterator for ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    type Item = T;
+    type IntoIter = IntoIter<T, K>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            back: self.len(),
+            storage: self,
+            front: 0,
+        }
+    }
+}
+impl<'a, T, K> IntoIterator for &'a ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+impl<'a, T, K> IntoIterator for &'a mut ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, K> ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// Collects the values into a new, growing storage, along with their keys in
+    /// iteration order.
+    pub fn from_iter_with_keys<I: IntoIterator<Item = T>>(iter: I) -> (Self, Vec<K>) {
+        let iter = iter.into_iter();
+        let mut storage = Self::with_key(iter.size_hint().0, GrowBehavior::Doubling);
+        let keys = iter
+            .map(|value| storage.add(value).expect("ContigStorage failed to grow"))
+            .collect();
+        (storage, keys)
+    }
+    /// Adds every value, discarding the keys, and stops at the first one that can't be
+    /// added, returning why. The values before it stay added. Pass `iter.by_ref()` to
+    /// keep the values after it.
+    pub fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), ContigError> {
+        for value in iter {
+            self.add(value)?;
+        }
+        Ok(())
+    }
+}
+impl<T, K> FromIterator<T> for ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// The storage grows with `GrowBehavior::Doubling`. The keys are lost; see
+    /// `from_iter_with_keys` to keep them.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from_iter_with_keys(iter).0
+    }
+}
+impl<T, K> Extend<T> for ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// Adds every value, discarding the keys. Panics if the storage fills up, which
+    /// with `GrowBehavior::None` happens at the capacity; `try_extend` returns an error
+    /// instead.
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.try_extend(iter).expect("ContigStorage full while extending");
+    }
+}
+impl<'a, T, K> Extend<&'a T> for ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied())
+    }
+}
@@ -1,11 +1,59 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
 use bit_vec::BitVec;
+use core::fmt::{self, Debug};
+use core::marker::PhantomData;
+use core::num::NonZeroUsize;
+#[cfg(target_has_atomic = "ptr")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "rand")]
 use rand::Rng;
-use std::fmt::{self, Debug};
+use slots::{Slots, SlotsMut};
+use tracked::Tracked;
+
+pub use array::ArrayContigStorage;
+pub use bucketed::BucketedContigStorage;
+pub use buffered::{BackBuffer, BufferedContigStorage, Frame};
+pub use iter::{ContigDrain, IntoIter, Iter, IterMut};
+pub use sorted::{SortKey, SortedContigStorage};
+pub use transaction::Transaction;
+#[cfg(feature = "std")]
+pub use concurrent::{ConcurrentContigStorage, ReadGuard};
 
+mod array;
+mod bucketed;
+mod buffered;
+#[cfg(feature = "bytemuck")]
+mod bytes;
+#[cfg(feature = "std")]
+mod concurrent;
+mod feistel;
+mod iter;
+#[cfg(any(test, feature = "arbitrary"))]
+pub mod fuzzing;
+#[cfg(feature = "rayon")]
+mod par;
+mod reorder;
+mod slots;
+mod sorted;
+#[cfg(feature = "std")]
+mod sync;
+mod trace;
+mod tracked;
+mod transaction;
 #[cfg(test)]
 mod tests;
 
+// A slot of the storage. Slots below `len` hold a `value`, all others an `indirection`
+// (or NOTHING), always written through the whole `usize`. Which field is live is thus
+// known from the index alone, and the padding of a `T` is never read as a `usize`.
+// `repr(C)` places both fields at offset 0, which `get_slice` relies on.
 #[derive(Copy, Clone)]
+#[repr(C)]
 union Item<T: Copy + Clone> {
     value: T,
     indirection: usize,
@@ -14,12 +62,13 @@ impl<T> Item<T>
 where
     T: Copy,
 {
-    // const NOTHING_MASK: usize = std::usize::MAX ^ (std::usize::MAX >> 1);
+    // const NOTHING_MASK: usize = usize::MAX ^ (usize::MAX >> 1);
     const NOTHING: usize = 0;
     const NOTHING_ITEM: Self = Self {
         indirection: Self::NOTHING,
     };
 
+    // Both readers require that the slot is at or past `len`, so that it doesn't hold a value.
     unsafe fn get_indirection(&self) -> usize {
         self.indirection.wrapping_sub(1)
     }
@@ -35,15 +84,50 @@ where
 }
 
 
-// THIS IS ONLY HERE SO I CAN CHANGE THE API MORE EASILY IF I NEED TO
-pub type Key = usize;
-trait Keylike {
-    fn key_wrap(x: usize) -> Self;
-    fn key_unwrap(self) -> Self;
+/// A handle type for the values of one kind of `ContigStorage`. Giving each storage
+/// its own key type (see `new_key_type!`) turns mixing up keys into a compile error.
+/// Keys are never zero, so `Option` of a key built on `NonZeroUsize` is one word.
+pub trait Keylike: Copy + Eq + Debug {
+    fn key_wrap(x: NonZeroUsize) -> Self;
+    fn key_unwrap(self) -> NonZeroUsize;
 }
-impl Keylike for Key {
-    fn key_wrap(x: usize) -> Self { x }
-    fn key_unwrap(self) -> Self { self }
+
+/// Declares one or more new key types implementing `Keylike`.
+///
+/// ```compile_fail
+/// use contig_storage::{new_key_type, ContigStorage, GrowBehavior};
+/// new_key_type! {
+///     pub struct MeshKey;
+///     pub struct MaterialKey;
+/// }
+/// let mut meshes = ContigStorage::<u64, MeshKey>::with_key(8, GrowBehavior::None);
+/// let materials = ContigStorage::<u64, MaterialKey>::with_key(8, GrowBehavior::None);
+/// let mesh = meshes.add(1).unwrap();
+/// materials.get(mesh); // mismatched types
+/// ```
+#[macro_export]
+macro_rules! new_key_type {
+    ( $(#[$outer:meta])* $vis:vis struct $name:ident; $($rest:tt)* ) => {
+        $(#[$outer])*
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[repr(transparent)]
+        $vis struct $name(::core::num::NonZeroUsize);
+        impl $crate::Keylike for $name {
+            fn key_wrap(x: ::core::num::NonZeroUsize) -> Self {
+                $name(x)
+            }
+            fn key_unwrap(self) -> ::core::num::NonZeroUsize {
+                self.0
+            }
+        }
+        $crate::new_key_type!($($rest)*);
+    };
+    () => {};
+}
+
+new_key_type! {
+    /// The key type of a `ContigStorage` created without choosing one.
+    pub struct Key;
 }
 
 
@@ -60,72 +144,300 @@ pub enum GrowBehavior {
     None,
 }
 
+/// What `remove` does with the hole a value leaves in the dense region.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RemovePolicy {
+    /// Moves the last value into the hole. O(1), but reorders the slice.
+    SwapRemove,
+    /// Shifts every later value down by one, keeping the slice in insertion order. O(n)
+    /// in the values after the hole. The first `add` or `remove` under this policy
+    /// switches the storage to a key table, in time linear in the capacity, which it
+    /// keeps until the next `clear` or `assign_new_keys`.
+    ///
+    /// The key table costs memory on top of the slots: the slot of every key index and
+    /// the key index of every slot in use, one `usize` each, plus a queue of the free key
+    /// indices. That is two words per slot of capacity, and up to three while the table's
+    /// buffers keep spare room after removals. `Stats::key_table` tells whether a storage
+    /// has one. Sorting, `swap`, `move_to_index` and `deactivate` switch to it as well.
+    ShiftPreserveOrder,
+}
+
+/// Everything that can go wrong in a `ContigStorage` operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ContigError {
+    /// `add` on a full storage that may not grow (any further).
+    Full,
+    /// `add` needed to grow the storage, but the allocation failed.
+    AllocFailed,
+    /// The key cannot have been issued by this storage in its current state, e.g. it
+    /// decodes to a slot beyond the capacity.
+    InvalidKey,
+    /// The key names a slot whose value has been removed, or invalidated by `clear`,
+    /// `invalidate_keys` or `assign_new_keys`.
+    StaleKey,
+    /// The key names a slot that now holds a value only reachable through another key.
+    /// The key itself is stale.
+    IndirectOnlyAccess,
+    /// The key was issued by another storage. Only detected with instance tagging.
+    WrongStorage,
+    /// A slot index not below `len` was given for a value to move to.
+    OutOfBounds,
+    /// `swap` or `move_to_index` would move a value between the active prefix and the
+    /// inactive rest, which only `activate` and `deactivate` do.
+    CrossesActivePartition,
+    /// The storage's bookkeeping is inconsistent.
+    Corrupted,
+}
+impl fmt::Display for ContigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str(match self {
+            ContigError::Full => "storage is full",
+            ContigError::AllocFailed => "failed to allocate storage",
+            ContigError::InvalidKey => "invalid key",
+            ContigError::StaleKey => "stale key",
+            ContigError::IndirectOnlyAccess => "stale key to a slot only reachable indirectly",
+            ContigError::WrongStorage => "key of another storage",
+            ContigError::OutOfBounds => "slot index out of bounds",
+            ContigError::CrossesActivePartition => "move across the active partition",
+            ContigError::Corrupted => "storage is corrupted",
+        })
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for ContigError {}
+
+/// An invariant of `ContigStorage` or `ArrayContigStorage` found violated by `validate`.
+/// Where it makes sense, the offending slot is included.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Corruption {
+    /// The slot buffer, `indirect_only_bitfield` or the generations of keyed keys
+    /// disagree with the capacity.
+    CapacityMismatch,
+    /// `active_len <= len <= start_of_clean <= capacity` does not hold, or `active_len`
+    /// is below `len` without a key table.
+    BoundsOutOfOrder,
+    /// A slot at or past `start_of_clean` is not `NOTHING`.
+    UncleanSlot(usize),
+    /// An indirection does not point to a lower slot, so chains may not terminate.
+    ForwardIndirection(usize),
+    /// An indirection chain ends in `NOTHING` rather than below `len`.
+    DanglingIndirection(usize),
+    /// A slot is the target of more than one indirection.
+    SharedTarget(usize),
+    /// A slot's bit in `indirect_only_bitfield` disagrees with whether an indirection
+    /// points to it.
+    IndirectOnlyMismatch(usize),
+    /// The table mapping keys to slots, kept under `RemovePolicy::ShiftPreserveOrder`,
+    /// is not a bijection between the dense region and the keys in use.
+    KeyTableMismatch(usize),
+}
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Corruption::CapacityMismatch => f.write_str("buffers disagree on the capacity"),
+            Corruption::BoundsOutOfOrder => f.write_str("active_len <= len <= start_of_clean <= capacity violated, or inactive values without a key table"),
+            Corruption::UncleanSlot(i) => write!(f, "slot {} past start_of_clean is not empty", i),
+            Corruption::ForwardIndirection(i) => write!(f, "indirection in slot {} does not point down", i),
+            Corruption::DanglingIndirection(i) => write!(f, "indirection chain from slot {} ends in nothing", i),
+            Corruption::SharedTarget(i) => write!(f, "slot {} is the target of several indirections", i),
+            Corruption::IndirectOnlyMismatch(i) => write!(f, "slot {} has the wrong indirect-only bit", i),
+            Corruption::KeyTableMismatch(i) => write!(f, "key table entry {} is inconsistent", i),
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for Corruption {}
+impl From<Corruption> for ContigError {
+    fn from(_: Corruption) -> Self {
+        ContigError::Corrupted
+    }
+}
+
+/// How much indirection a storage has accumulated, see `ContigStorage::stats`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub len: usize,
+    pub capacity: usize,
+    /// Slots at and past this one have never been used since the last clear or rekey.
+    pub start_of_clean: usize,
+    /// Slots past `len` holding an indirection.
+    pub indirections: usize,
+    /// Slots below `len` whose value can only be reached through an indirection.
+    pub indirect_only: usize,
+    /// The most indirections followed to resolve any key.
+    pub max_chain_length: usize,
+    /// How often the storage grew since it was created.
+    pub grow_count: usize,
+    /// Whether keys are resolved through a key table instead of indirections, see
+    /// `RemovePolicy::ShiftPreserveOrder`. While they are, `indirections`,
+    /// `indirect_only` and `max_chain_length` are 0.
+    pub key_table: bool,
+}
+
+/// How slot indices are turned into the keys handed out by a `ContigStorage`.
+/// Both are rerolled by `clear`, `invalidate_keys` and `assign_new_keys`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyEncoding {
+    /// Index XOR a random mask. Cheapest, but anyone holding two keys can recover
+    /// the mask and compute the key of any other slot.
+    Xor,
+    /// Index through a Feistel network keyed with a secret 128-bit SipHash key.
+    /// Costs a few hashes per lookup. Seeing issued keys does not help to forge
+    /// others: a made-up key names a live entry with probability ~len / 2^usize::BITS.
+    ///
+    /// Along with the index, keys encode a generation of usize::BITS / 4 bits, bumped
+    /// whenever the value of that index is removed. A removed key thus stays stale when
+    /// its index is handed out again, until the index has been reused 2^(usize::BITS / 4)
+    /// times. This takes a `u16` per slot, and limits the capacity to
+    /// 2^(usize::BITS * 3 / 4) - 1, or 2^(usize::BITS / 2) - 1 with instance tagging.
+    Keyed,
+}
+
+// With instance tagging, the top TAG_BITS bits of a key hold the storage's instance id
+// and the slot index is encoded into the remaining PAYLOAD_BITS.
+const TAG_BITS: u32 = usize::BITS / 4;
+const PAYLOAD_BITS: u32 = usize::BITS - TAG_BITS;
+// With `KeyEncoding::Keyed`, the top GENERATION_BITS bits of the payload hold the
+// generation of the key index, and the index is encoded below them.
+const GENERATION_BITS: u32 = usize::BITS / 4;
+const GENERATION_MASK: u16 = (usize::MAX >> (usize::BITS - GENERATION_BITS)) as u16;
+// Instance ids run from 1 to the largest tag, so that no tagged key has a zero tag.
+const MAX_INSTANCE_ID: usize = usize::MAX >> PAYLOAD_BITS;
+#[cfg(target_has_atomic = "ptr")]
+static NEXT_INSTANCE_ID: AtomicUsize = AtomicUsize::new(1);
+#[cfg(target_has_atomic = "ptr")]
+fn next_instance_id(_seed: u64) -> usize {
+    NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed).wrapping_sub(1) % MAX_INSTANCE_ID + 1
+}
+// Without compare-and-swap there is no counter to share, so the id is drawn from the
+// seed: storages seeded differently most likely get different ids.
+#[cfg(not(target_has_atomic = "ptr"))]
+fn next_instance_id(seed: u64) -> usize {
+    KeyRng(!seed).next_u64() as usize % MAX_INSTANCE_ID + 1
+}
+
+// Source of `indirection_xor` and `key_secret`. This is a splitmix64 stream rather than
+// a `rand` generator because its output is fixed across platforms and crate versions:
+// two storages seeded alike and fed the same operations hand out the same keys.
 #[derive(Debug, Copy, Clone)]
-pub struct FullError;
+struct KeyRng(u64);
+impl KeyRng {
+    const fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(feature = "rand")]
+fn fresh_seed() -> u64 {
+    rand::thread_rng().gen()
+}
+#[cfg(all(not(feature = "rand"), target_has_atomic = "ptr"))]
+fn fresh_seed() -> u64 {
+    static NEXT_SEED: AtomicUsize = AtomicUsize::new(0);
+    KeyRng(NEXT_SEED.fetch_add(1, Ordering::Relaxed) as u64).next_u64()
+}
+// Without compare-and-swap there is no counter either, see `new`.
+#[cfg(all(not(feature = "rand"), not(target_has_atomic = "ptr")))]
+fn fresh_seed() -> u64 {
+    KeyRng(0).next_u64()
+}
 
-pub struct ContigStorage<T: Copy> {
+/// Clones keep the slot layout, key scrambling and instance identifier of the original,
+/// so every key of one resolves to the same value in the other.
+#[derive(Clone)]
+pub struct ContigStorage<T: Copy, K: Keylike = Key> {
     data: Vec<Item<T>>,
     len: usize,
+    // values below it are active, the others inactive. Below `len` only while tracked.
+    active_len: usize,
     start_of_clean: usize,
     indirection_xor: usize,
+    key_secret: [u64; 2],
+    key_encoding: KeyEncoding,
+    // per key index, bumped when its value is removed. Empty unless keyed.
+    generations: Vec<u16>,
+    instance_id: usize,
+    instance_tagging: bool,
     indirect_only_bitfield: BitVec,
+    key_rng: KeyRng,
+    grow_count: usize,
+    // replaces the indirection chains once values were moved in ways they can't express
+    tracked: Option<Tracked>,
+    _key: PhantomData<K>,
     pub grow_behavior: GrowBehavior,
+    pub remove_policy: RemovePolicy,
 }
-impl<T> Debug for ContigStorage<T>
+impl<T, K> Debug for ContigStorage<T, K>
 where
     T: Copy + Debug,
+    K: Keylike,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        for i in 0..self.capacity() {
-            let x = if self.indirect_only_bitfield.get(i).unwrap() {
-                "@"
-            } else {
-                " "
-            };
-            f.write_fmt(format_args!("{: >3}{}", i, x))?
-        }
-        f.write_str("\n[")?;
-        for i in 0..self.capacity() {
-            if i > 0 {
-                f.write_str(if i == self.len { "|" } else { "," })?;
-            }
-            match self.slot_contents(i) {
-                SlotContents::Data => unsafe { self.data[i].value.fmt(f)? },
-                SlotContents::Indirection => f.write_fmt(format_args!("@{: <2}", unsafe {
-                    self.data[i].get_indirection()
-                }))?,
-                SlotContents::Nothing => f.write_str(" _ ")?,
-            }
-        }
-        f.write_fmt(format_args!(
-            "] len: {}, xor: {:X}\n",
-            self.len, self.indirection_xor
-        ))
+        self.slots().fmt(f)?;
+        f.write_fmt(format_args!(", xor: {:X}\n", self.indirection_xor))
     }
 }
 impl<T> ContigStorage<T>
 where
     T: Copy,
 {
+    /// Creates a storage whose keys are scrambled with fresh entropy from `thread_rng`.
+    /// Without the `rand` feature, the seed comes from a global counter instead: every
+    /// storage still gets its own keys, but they are predictable. Prefer `new_with_seed`
+    /// with some entropy of your own then. On targets without compare-and-swap, such as
+    /// `thumbv6m`, there is no such counter and every storage gets the same seed, and
+    /// instance ids are drawn from the seed, so `new_with_seed` is the way to tell
+    /// storages apart.
+    pub fn new(capacity: usize, grow_behavior: GrowBehavior) -> Self {
+        Self::new_with_seed(capacity, grow_behavior, fresh_seed())
+    }
+    /// Like `new`, but the seed is drawn from the given RNG.
+    #[cfg(feature = "rand")]
+    pub fn new_with_rng<R: Rng + ?Sized>(
+        capacity: usize,
+        grow_behavior: GrowBehavior,
+        rng: &mut R,
+    ) -> Self {
+        Self::new_with_seed(capacity, grow_behavior, rng.gen())
+    }
+    /// Like `with_key_and_seed`, for the default key type.
+    pub fn new_with_seed(capacity: usize, grow_behavior: GrowBehavior, seed: u64) -> Self {
+        Self::with_key_and_seed(capacity, grow_behavior, seed)
+    }
+}
+impl<T, K> ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    // The value accessors must only be used on slots below `len`.
     fn copy_value(&self, index: usize) -> T {
-        // HERE THERE BE UNSAFETY
-        unsafe {
-            self.data[index].value
-            // self.data.get_unchecked(index).value
-        }
+        *self.get_value(index)
     }
     fn get_value(&self, index: usize) -> &T {
-        // HERE THERE BE UNSAFETY
-        unsafe {
-            &self.data[index].value
-            // &self.data.get_unchecked(index).value
-        }
+        self.slots().value(index)
     }
     fn get_mut_value(&mut self, index: usize) -> &mut T {
-        // HERE THERE BE UNSAFETY
-        unsafe {
-            &mut self.data[index].value
-            // &mut self.data.get_unchecked_mut(index).value
+        self.slots_mut().into_value_mut(index)
+    }
+    fn slots(&self) -> Slots<'_, T, BitVec> {
+        Slots {
+            data: &self.data,
+            len: self.len,
+            start_of_clean: self.start_of_clean,
+            flags: &self.indirect_only_bitfield,
+        }
+    }
+    fn slots_mut(&mut self) -> SlotsMut<'_, T, BitVec> {
+        SlotsMut {
+            data: &mut self.data,
+            len: &mut self.len,
+            start_of_clean: &mut self.start_of_clean,
+            flags: &mut self.indirect_only_bitfield,
         }
     }
 
@@ -138,262 +450,503 @@ where
     pub fn capacity(&self) -> usize {
         self.data.len()
     }
-    pub fn new(mut capacity: usize, grow_behavior: GrowBehavior) -> Self {
-        if std::mem::size_of::<Item<T>>() > std::mem::size_of::<T>() {
-            panic!("Cannot store contiguously! Size of type ({} bytes) < size of usize ({})",
-                std::mem::size_of::<T>(),
-                std::mem::size_of::<Item<T>>());
-        }
-
-        if capacity == std::usize::MAX {
+    /// Creates a storage handing out keys of type `K`.
+    pub fn with_key(capacity: usize, grow_behavior: GrowBehavior) -> Self {
+        Self::with_key_and_seed(capacity, grow_behavior, fresh_seed())
+    }
+    /// Like `with_key`, but keys are a pure function of `seed` and the sequence of
+    /// calls made on it. `clear`, `invalidate_keys` and `assign_new_keys` reroll from
+    /// the same seeded stream, so replaying the calls reproduces every key.
+    pub fn with_key_and_seed(mut capacity: usize, grow_behavior: GrowBehavior, seed: u64) -> Self {
+        if capacity == usize::MAX {
             capacity -= 1;
         }
-        Self {
-            data: std::iter::repeat(Item::NOTHING_ITEM).take(capacity).collect(),
+        let mut storage = Self {
+            data: vec![Item::NOTHING_ITEM; capacity],
             len: 0,
+            active_len: 0,
             grow_behavior,
             start_of_clean: 0,
-            indirection_xor: rand::thread_rng().gen(),
+            indirection_xor: 0,
+            key_secret: [0; 2],
+            key_encoding: KeyEncoding::Xor,
+            generations: Vec::new(),
+            instance_id: next_instance_id(seed),
+            instance_tagging: false,
             indirect_only_bitfield: BitVec::from_elem(capacity, false),
-        }
+            key_rng: KeyRng(seed),
+            grow_count: 0,
+            tracked: None,
+            _key: PhantomData,
+            remove_policy: RemovePolicy::SwapRemove,
+        };
+        storage.invalidate_keys();
+        storage
+    }
+    /// Restarts the stream that key rerolls are drawn from. Rerolls immediately,
+    /// invalidating all keys, so the keys issued afterwards depend only on `seed`.
+    pub fn reseed(&mut self, seed: u64) {
+        self.key_rng = KeyRng(seed);
+        self.invalidate_keys();
     }
     fn slot_contents(&self, index: usize) -> SlotContents {
-        if index < self.len {
-            SlotContents::Data
-        } else if unsafe { self.data[index].is_nothing() } {
-            SlotContents::Nothing
-        } else {
-            SlotContents::Indirection
-        }
+        self.slots().slot_contents(index)
+    }
+    fn indirection_target(&self, index: usize) -> usize {
+        self.slots().indirection_target(index)
     }
     pub fn clear(&mut self) {
-        for x in self.data[0..self.start_of_clean].iter_mut() {
-            *x = Item::<T>::NOTHING_ITEM;
+        let _span = trace::clear(self.len, self.capacity());
+        self.slots_mut().clear();
+        self.active_len = 0;
+        self.tracked = None;
+        self.invalidate_keys();
+        self.check_invariants();
+    }
+
+    /// Checks every invariant of the internal bookkeeping, in time linear in the capacity.
+    /// Meant for debugging and fuzzing; a storage only used through its API always passes.
+    pub fn validate(&self) -> Result<(), Corruption> {
+        let capacity = self.capacity();
+        let generations = match self.key_encoding {
+            KeyEncoding::Xor => 0,
+            KeyEncoding::Keyed => capacity,
+        };
+        if self.indirect_only_bitfield.len() != capacity || self.generations.len() != generations {
+            return Err(Corruption::CapacityMismatch);
+        }
+        if let Some(tracked) = &self.tracked {
+            // there are no chains left, which the checks below confirm
+            self.validate_tracked(tracked)?;
+        }
+        if self.active_len > self.len || self.active_len < self.len && self.tracked.is_none() {
+            return Err(Corruption::BoundsOutOfOrder);
+        }
+        self.slots().validate()
+    }
+    /// Measures the indirection overhead, in time linear in `start_of_clean`. Long chains
+    /// slow lookups down; `assign_new_keys` removes all of them.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            grow_count: self.grow_count,
+            key_table: self.tracked.is_some(),
+            ..self.slots().stats()
+        }
+    }
+    // With the `debug-invariants` feature, panics if `validate` fails. Run after every
+    // call that touches the bookkeeping.
+    fn check_invariants(&self) {
+        #[cfg(feature = "debug-invariants")]
+        {
+            if let Err(corruption) = self.validate() {
+                panic!("ContigStorage corrupted: {}", corruption);
+            }
         }
-        self.len = 0;
-        self.start_of_clean = 0;
-        self.indirection_xor = rand::thread_rng().gen();
-        self.indirect_only_bitfield.set_all();
-        self.indirect_only_bitfield.negate();
     }
     pub fn invalidate_keys(&mut self) {
-        self.indirection_xor = rand::thread_rng().gen();
+        self.indirection_xor = self.key_rng.next_u64() as usize;
+        self.key_secret = [self.key_rng.next_u64(), self.key_rng.next_u64()];
+    }
+    pub fn key_encoding(&self) -> KeyEncoding {
+        self.key_encoding
+    }
+    /// Switches how keys are encoded. Invalidates all keys, as `invalidate_keys` does.
+    /// Panics, leaving the storage as it was, if the capacity is above the limit of
+    /// `KeyEncoding::Keyed`.
+    pub fn set_key_encoding(&mut self, key_encoding: KeyEncoding) {
+        assert!(
+            self.capacity() <= Self::max_capacity_with(self.instance_tagging, key_encoding),
+            "Capacity too large for keyed encoding"
+        );
+        self.key_encoding = key_encoding;
+        self.generations = match key_encoding {
+            KeyEncoding::Xor => Vec::new(),
+            KeyEncoding::Keyed => vec![0; self.capacity()],
+        };
+        self.invalidate_keys();
+    }
+    pub fn instance_tagging(&self) -> bool {
+        self.instance_tagging
+    }
+    /// Makes keys carry an identifier of this storage, so that the `try_*` methods report
+    /// keys of other storages as `ContigError::WrongStorage` instead of possibly resolving
+    /// them to some value. Identifiers only repeat once 2^(usize::BITS / 4) - 1 storages
+    /// have been created, and while enabled the capacity is limited to 2^(usize::BITS * 3 / 4) - 1.
+    /// Invalidates all keys, as `invalidate_keys` does.
+    /// Panics, leaving the storage as it was, if the capacity is above that limit.
+    pub fn set_instance_tagging(&mut self, instance_tagging: bool) {
+        assert!(
+            self.capacity() <= Self::max_capacity_with(instance_tagging, self.key_encoding),
+            "Capacity too large for instance tagging"
+        );
+        self.instance_tagging = instance_tagging;
+        self.invalidate_keys();
     }
-    pub fn assign_new_keys(&mut self) -> impl Iterator<Item=Key> + '_ {
-        println!("LEN IS {} SOC IS {}", self.len, self.start_of_clean);
-        //TODO test 
+    fn max_capacity(&self) -> usize {
+        Self::max_capacity_with(self.instance_tagging, self.key_encoding)
+    }
+    fn max_capacity_with(instance_tagging: bool, key_encoding: KeyEncoding) -> usize {
+        match Self::index_bits_with(instance_tagging, key_encoding) {
+            usize::BITS => usize::MAX - 1,
+            bits => (1 << bits) - 1,
+        }
+    }
+    // The bits of the payload encoding the key index, below the generation if keyed.
+    fn index_bits_with(instance_tagging: bool, key_encoding: KeyEncoding) -> u32 {
+        let payload_bits = if instance_tagging { PAYLOAD_BITS } else { usize::BITS };
+        match key_encoding {
+            KeyEncoding::Xor => payload_bits,
+            KeyEncoding::Keyed => payload_bits - GENERATION_BITS,
+        }
+    }
+    fn payload_bits(&self) -> u32 {
+        if self.instance_tagging {
+            PAYLOAD_BITS
+        } else {
+            usize::BITS
+        }
+    }
+    // A bijection on the low `bits` bits, undone by `unpermute`.
+    fn permute(&self, bits: u32, x: usize) -> usize {
+        match self.key_encoding {
+            KeyEncoding::Xor => (x ^ self.indirection_xor) & (usize::MAX >> (usize::BITS - bits)),
+            KeyEncoding::Keyed => feistel::encrypt(&self.key_secret, bits, x),
+        }
+    }
+    fn unpermute(&self, bits: u32, x: usize) -> usize {
+        match self.key_encoding {
+            KeyEncoding::Xor => (x ^ self.indirection_xor) & (usize::MAX >> (usize::BITS - bits)),
+            KeyEncoding::Keyed => feistel::decrypt(&self.key_secret, bits, x),
+        }
+    }
+    // Like `Item`, keys store index + 1 so that zero is never a key and `Option<K>` stays
+    // one word. The permutation restricted to nonzero values is made a bijection again by
+    // cycle walking: if a nonzero value lands on zero, permute once more. Zero is hit by
+    // exactly one input, so one extra step always suffices.
+    fn index_to_key(&self, index: usize) -> K {
+        let bits = self.payload_bits();
+        let mut plain = index + 1;
+        if self.key_encoding == KeyEncoding::Keyed {
+            let index_bits = Self::index_bits_with(self.instance_tagging, self.key_encoding);
+            plain |= (self.generations[index] as usize) << index_bits;
+        }
+        let mut payload = self.permute(bits, plain);
+        if payload == 0 {
+            payload = self.permute(bits, payload);
+        }
+        let raw = if self.instance_tagging {
+            (self.instance_id << PAYLOAD_BITS) | payload
+        } else {
+            payload
+        };
+        K::key_wrap(NonZeroUsize::new(raw).expect("key payload is never zero"))
+    }
+    fn key_to_index(&self, key: K) -> Result<usize, ContigError> {
+        let key = key.key_unwrap().get();
+        if self.instance_tagging && key >> PAYLOAD_BITS != self.instance_id {
+            return Err(ContigError::WrongStorage);
+        }
+        let bits = self.payload_bits();
+        let payload = key & (usize::MAX >> (usize::BITS - bits));
+        if payload == 0 {
+            return Err(ContigError::InvalidKey);
+        }
+        let mut x = self.unpermute(bits, payload);
+        if x == 0 {
+            x = self.unpermute(bits, x);
+        }
+        if self.key_encoding == KeyEncoding::Keyed {
+            let index_bits = Self::index_bits_with(self.instance_tagging, self.key_encoding);
+            let generation = x >> index_bits;
+            x &= usize::MAX >> (usize::BITS - index_bits);
+            if x == 0 {
+                return Err(ContigError::InvalidKey);
+            }
+            // past the capacity, resolving reports the key as invalid
+            if let Some(&current) = self.generations.get(x - 1) {
+                if current as usize != generation {
+                    return Err(ContigError::StaleKey);
+                }
+            }
+        }
+        Ok(x - 1)
+    }
+    pub fn assign_new_keys(
+        &mut self,
+    ) -> impl ExactSizeIterator<Item = K> + DoubleEndedIterator + '_ {
+        let _span = trace::rekey(self.len, self.start_of_clean);
         for x in self.data[self.len..self.start_of_clean].iter_mut() {
             *x = Item::<T>::NOTHING_ITEM;
         }
         self.start_of_clean = self.len;
-        self.indirection_xor = rand::thread_rng().gen();
+        self.tracked = None;
+        self.invalidate_keys();
         self.indirect_only_bitfield.set_all();
         self.indirect_only_bitfield.negate();
+        if self.active_len < self.len {
+            // with no chains left, the table maps each new key to its own slot
+            self.track();
+        }
+        self.check_invariants();
 
         (0..self.len)
-        .map(move |i| Key::key_wrap(i ^ self.indirection_xor))
+        .map(move |i| self.index_to_key(i))
     }
-    pub fn add(&mut self, value: T) -> Result<Key,FullError> {
-        // println!("ADD");
+    pub fn add(&mut self, value: T) -> Result<K, ContigError> {
+        let result = self.add_value(value);
+        self.check_invariants();
+        result
+    }
+    fn add_value(&mut self, value: T) -> Result<K, ContigError> {
         if self.len >= self.capacity() {
             if GrowBehavior::None == self.grow_behavior
-            || self.capacity() == std::usize::MAX-1 {
-                return Err(FullError);
+            || self.capacity() >= self.max_capacity() {
+                return Err(ContigError::Full);
             } else {
-                // grow!
-                let start = std::time::Instant::now();
-                let new_capacity = self.capacity().saturating_add(2).saturating_mul(2).min(std::usize::MAX-1);
-                let mut new_data = Vec::with_capacity(new_capacity);
-                unsafe { new_data.set_len(new_capacity); }
-                let e = self.len;
-                for (src, dest) in self.data[0..e].iter().zip(new_data[0..e].iter_mut()) {
-                    *dest = *src;
+                // grow! len == capacity, so there are no indirections to carry over
+                let old_capacity = self.capacity();
+                let new_capacity = old_capacity.saturating_add(2).saturating_mul(2).min(self.max_capacity());
+                let _span = trace::grow(old_capacity, new_capacity);
+                // the new slots are written as NOTHING before anything can read them
+                self.data
+                    .try_reserve_exact(new_capacity - old_capacity)
+                    .map_err(|_| ContigError::AllocFailed)?;
+                if self.key_encoding == KeyEncoding::Keyed {
+                    self.generations
+                        .try_reserve_exact(new_capacity - old_capacity)
+                        .map_err(|_| ContigError::AllocFailed)?;
+                    self.generations.resize(new_capacity, 0);
                 }
-                for dest in new_data[e..].iter_mut() {
-                    *dest = Item::NOTHING_ITEM;
+                self.data.resize(new_capacity, Item::NOTHING_ITEM);
+                self.indirect_only_bitfield.grow(new_capacity - old_capacity, false);
+                if let Some(tracked) = &mut self.tracked {
+                    tracked.grow(new_capacity);
                 }
-                self.data = new_data;
-                self.indirect_only_bitfield.grow(new_capacity, false);
-                println!("{:?}", start.elapsed());
-                println!("GREW. new capacity is {}", self.capacity());
+                self.grow_count += 1;
             }
         }
-        let boundary = self.len;
-        self.start_of_clean = self.start_of_clean.max(self.len + 1);
-        match self.slot_contents(boundary) {
-            SlotContents::Nothing => {
-                self.data[boundary].value = value;
-                self.len += 1;
-                Ok(Key::key_wrap(boundary ^ self.indirection_xor))
-            }
-            SlotContents::Indirection => {
-                let real_location = unsafe { self.data[boundary].get_indirection() };
-                // make boundary a direct mapping
-                self.data[boundary].value = self.copy_value(real_location);
-                self.indirect_only_bitfield.set(boundary, false);
-                // occupy the data previously reached by the indirection
-                self.data[real_location].value = value;
-                self.indirect_only_bitfield.set(real_location, false);
-                self.len += 1;
-                Ok(Key::key_wrap(real_location ^ self.indirection_xor))
-            }
-            SlotContents::Data => {
-                panic!("Corruption! ContigStorage should NOT have data beyond the boundary!");
-            }
+        if self.remove_policy == RemovePolicy::ShiftPreserveOrder || self.active_len < self.len {
+            // an add into an indirection would move an existing value to the end
+            self.track();
         }
-    }
-
-    // invoked when index < len and index now logically contains Nothing. len is unchanged
-    fn fill_hole(&mut self, index: usize) {
-        let boundary = self.len - 1;
-        if boundary == index {
-            // removed the boundary!
-            self.data[index].set_nothing();
-            self.indirect_only_bitfield.set(index, false);
-        } else {
-            // boundary now contains a data lement that is LEFT of len
-            // must move boundary into my slot and put indirection there
-            self.data[index].value = self.copy_value(boundary);
-            self.indirect_only_bitfield.set(index, true);
-            self.data[boundary].set_indirection(index);
+        if self.tracked.is_some() {
+            let index = self.tracked_add(value);
+            return Ok(self.index_to_key(index));
         }
-        self.len -= 1;
+        let index = self.slots_mut().add(value)?;
+        self.active_len = self.len;
+        Ok(self.index_to_key(index))
     }
 
-    pub fn remove(&mut self, key: Key) -> Option<T> {
-        let index = key.key_unwrap() ^ self.indirection_xor;
+    pub fn remove(&mut self, key: K) -> Option<T> {
+        self.try_remove(key).ok()
+    }
+    pub fn try_remove(&mut self, key: K) -> Result<T, ContigError> {
+        let index = self.key_to_index(key)?;
+        self.resolve_key_index(index)?;
+        let value = self.remove_index(index).ok_or(ContigError::Corrupted);
+        self.check_invariants();
+        value
+    }
+    fn remove_index(&mut self, index: usize) -> Option<T> {
         if index >= self.capacity() {
             return None;
         }
-        match self.slot_contents(index) {
-            SlotContents::Nothing => None,
-            SlotContents::Indirection => {
-                let real_location = unsafe { self.data[index].get_indirection() };
-                self.data[index].set_nothing();
-                // recursive call
-                // next layer will think its a direct access. permit it!
-                self.indirect_only_bitfield.set(real_location, false);
-                self.remove(Key::key_wrap(real_location ^ self.indirection_xor))
-            }
-            SlotContents::Data => {
-                if self.indirect_only_bitfield.get(index).unwrap() {
-                    // no direct access allowed >=[
-                    return None;
-                }
-                let value = self.copy_value(index);
-                self.fill_hole(index);
-                Some(value)
+        let shift = self.remove_policy == RemovePolicy::ShiftPreserveOrder;
+        if shift || self.active_len < self.len {
+            self.track();
+        }
+        let value = if self.tracked.is_some() {
+            self.tracked_remove(index, shift)
+        } else {
+            // without inactive values, `active_len` follows `len`
+            let value = self.slots_mut().remove_index(index);
+            self.active_len = self.len;
+            value
+        };
+        if value.is_some() {
+            // the removed key stays stale once `index` is handed out again
+            if let Some(generation) = self.generations.get_mut(index) {
+                *generation = generation.wrapping_add(1) & GENERATION_MASK;
             }
         }
+        value
     }
 
-    pub fn get_mut(&mut self, key: Key) -> Option<&mut T> {
-        let index = key.key_unwrap() ^ self.indirection_xor;
-        if index >= self.capacity() {
-            return None;
-        }
-        match self.slot_contents(index) {
-            SlotContents::Nothing => None,
-            SlotContents::Indirection => {
-                let real_location = unsafe { self.data[index].get_indirection() };
-                self.get_mut(Key::key_wrap(real_location ^ self.indirection_xor))
-            }
-            SlotContents::Data => Some(self.get_mut_value(index)),
-        }
+    pub fn get_mut(&mut self, key: K) -> Option<&mut T> {
+        self.try_get_mut(key).ok()
+    }
+    pub fn try_get_mut(&mut self, key: K) -> Result<&mut T, ContigError> {
+        let index = self.resolve_key_index(self.key_to_index(key)?)?;
+        Ok(self.get_mut_value(index))
     }
 
-    pub fn get(&self, key: Key) -> Option<&T> {
-        let index = key.key_unwrap() ^ self.indirection_xor;
+    pub fn get(&self, key: K) -> Option<&T> {
+        self.try_get(key).ok()
+    }
+    pub fn try_get(&self, key: K) -> Result<&T, ContigError> {
+        let index = self.resolve_key_index(self.key_to_index(key)?)?;
+        Ok(self.get_value(index))
+    }
+    // Resolves the index a key decoded to, to the slot holding its value.
+    fn resolve_key_index(&self, index: usize) -> Result<usize, ContigError> {
         if index >= self.capacity() {
-            return None;
+            return Err(ContigError::InvalidKey);
         }
-        match self.slot_contents(index) {
-            SlotContents::Nothing => None,
-            SlotContents::Indirection => {
-                let real_location = unsafe { self.data[index].get_indirection() };
-                self.get(Key::key_wrap(real_location ^ self.indirection_xor))
-            }
-            SlotContents::Data => Some(self.get_value(index)),
+        if let Some(tracked) = &self.tracked {
+            return match tracked.slot_of[index] {
+                tracked::UNUSED => Err(ContigError::StaleKey),
+                slot => Ok(slot),
+            };
         }
+        self.slots().resolve_key_index(index)
     }
 
+    /// The values as one slice. Panics if `T` is smaller than a `usize`: slots are then
+    /// wider than a `T` so they can hold indirections, and aren't laid out as a `[T]`.
+    /// Such storages are otherwise fully usable, through keys and the iterators.
     pub fn get_slice(&self) -> &[T] {
-        unsafe {
-            &*(&self.data[..self.len] as *const [Item<T>] as *const [T])
-        }
+        self.slots().values()
     }
 
-    pub fn get_slice_index(&self, key: Key) -> Option<usize> {
-        let index = key.key_unwrap() ^ self.indirection_xor;
-        if index >= self.capacity() {
-            return None;
+    /// Panics where `get_slice` does.
+    pub fn get_slice_mut(&mut self) -> &mut [T] {
+        self.slots_mut().into_values_mut()
+    }
+
+    pub fn get_slice_index(&self, key: K) -> Option<usize> {
+        self.resolve_key_index(self.key_to_index(key).ok()?).ok()
+    }
+
+    // For every slot in the dense region, the index its key decodes to. That is the slot
+    // itself, unless the value is reached through a chain of indirections, in which case
+    // it is the head of that chain: the indirection nothing else points to.
+    fn slot_owners(&self) -> Vec<usize> {
+        if let Some(tracked) = &self.tracked {
+            return tracked.key_of.clone();
         }
-        match self.slot_contents(index) {
-            SlotContents::Nothing => None,
-            SlotContents::Indirection => {
-                let real_location = unsafe { self.data[index].get_indirection() };
-                Some(real_location)
+        let mut owners: Vec<usize> = (0..self.len).collect();
+        let mut pointed_to = BitVec::from_elem(self.capacity(), false);
+        for i in self.len..self.start_of_clean {
+            if let SlotContents::Indirection = self.slot_contents(i) {
+                pointed_to.set(self.indirection_target(i), true);
+            }
+        }
+        for head in self.len..self.start_of_clean {
+            if let SlotContents::Indirection = self.slot_contents(head) {
+                if !pointed_to.get(head).unwrap() {
+                    if let Some(slot) = self.slots().resolve_index(head) {
+                        owners[slot] = head;
+                    }
+                }
             }
-            SlotContents::Data => Some(index),
         }
+        owners
+    }
+    /// Iterates over the dense region in slice order, along with the key of each value.
+    pub fn iter_with_keys(
+        &self,
+    ) -> impl ExactSizeIterator<Item = (K, &T)> + DoubleEndedIterator {
+        self.slot_owners()
+            .into_iter()
+            .zip(self.iter())
+            .map(move |(owner, value)| (self.index_to_key(owner), value))
     }
 
-    pub fn drain(&mut self) -> ContigDrain<T> {
-        ContigDrain(self, 0)
+    pub fn drain(&mut self) -> ContigDrain<'_, T, K> {
+        ContigDrain {
+            back: self.len,
+            storage: self,
+            front: 0,
+        }
+    }
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter(self.data[0..self.len].iter())
     }
-    pub fn iter(&self) -> impl Iterator<Item = &T> {
-        self.data[0..self.len]
-            .iter()
-            .map(|item| unsafe { &item.value })
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut(self.data[0..self.len].iter_mut())
     }
 }
 
-pub struct ContigDrain<'a, T>(&'a mut ContigStorage<T>, usize)
-where
-    T: Copy;
-impl<'a, T> Iterator for ContigDrain<'a, T>
+impl<T, K> core::ops::Index<K> for ContigStorage<T, K>
 where
     T: Copy,
+    K: Keylike,
 {
-    type Item = T;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.1 == self.0.len {
-            self.0.clear();
-            None
-        } else {
-            self.1 += 1;
-            Some(self.0.copy_value(self.1 - 1))
-        }
+    type Output = T;
+    fn index(&self, key: K) -> &T {
+        self.get(key)
+            .expect("ContigStorage indexed with invalid key.")
     }
 }
-
-impl<'a, T> IntoIterator for &'a ContigStorage<T>
+impl<T, K> core::ops::IndexMut<K> for ContigStorage<T, K>
 where
     T: Copy,
+    K: Keylike,
 {
-    type Item = &'a T;
-    type IntoIter = std::slice::Iter<'a, T>;
-    fn into_iter(self) -> Self::IntoIter {
-        self.get_slice().iter()
+    fn index_mut(&mut self, key: K) -> &mut T {
+        self.get_mut(key)
+            .expect("ContigStorage indexed with invalid key.")
     }
 }
 
-impl<T> std::ops::Index<Key> for ContigStorage<T>
+impl<T, K> Default for ContigStorage<T, K>
 where
     T: Copy,
+    K: Keylike,
 {
-    type Output = T;
-    fn index(&self, key: Key) -> &T {
-        self.get(key)
-            .expect("ContigStorage indexed with invalid key.")
+    /// An empty storage that grows with `GrowBehavior::Doubling`.
+    fn default() -> Self {
+        Self::with_key(0, GrowBehavior::Doubling)
     }
 }
-impl<T> std::ops::IndexMut<Key> for ContigStorage<T>
+impl<T, K> PartialEq for ContigStorage<T, K>
 where
-    T: Copy,
+    T: Copy + PartialEq,
+    K: Keylike,
 {
-    fn index_mut(&mut self, key: Key) -> &mut T {
-        self.get_mut(key)
-            .expect("ContigStorage indexed with invalid key.")
+    /// Compares the values as multisets, regardless of keys and slot layout. As `T` is
+    /// only `PartialEq`, values past the longest common prefix of the two slot orders
+    /// are matched up pairwise: linear in `len` for storages with the same slot order,
+    /// but quadratic in the number of values after the first difference. Sort both
+    /// storages the same way first to keep large comparisons linear.
+    fn eq(&self, other: &Self) -> bool {
+        if self.len != other.len {
+            return false;
+        }
+        let prefix = self.iter().zip(other.iter()).take_while(|(a, b)| a == b).count();
+        let mut matched = BitVec::from_elem(other.len - prefix, false);
+        self.iter().skip(prefix).all(|a| {
+            let found = other
+                .iter()
+                .skip(prefix)
+                .enumerate()
+                .position(|(i, b)| !matched.get(i).unwrap() && a == b);
+            found.map(|i| matched.set(i, true)).is_some()
+        })
+    }
+}
+impl<T, K> Eq for ContigStorage<T, K>
+where
+    T: Copy + Eq,
+    K: Keylike,
+{
+}
+impl<T, K> core::hash::Hash for ContigStorage<T, K>
+where
+    T: Copy + core::hash::Hash,
+    K: Keylike,
+{
+    /// Agrees with `PartialEq`: the values are hashed one by one and the hashes summed,
+    /// which doesn't depend on their order.
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        use core::hash::Hasher;
+        let sum = self.iter().fold(0u64, |sum, value| {
+            let mut hasher = siphasher::sip::SipHasher13::new();
+            value.hash(&mut hasher);
+            sum.wrapping_add(hasher.finish())
+        });
+        state.write_usize(self.len);
+        state.write_u64(sum);
     }
 }
@@ -0,0 +1,61 @@
+// Parallel iteration over the dense region with rayon.
+
+use crate::{ContigStorage, Keylike};
+use rayon::prelude::*;
+
+impl<T, K> ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = &T>
+    where
+        T: Sync,
+    {
+        // SAFETY: slots below `len` hold values
+        self.data[..self.len]
+            .par_iter()
+            .map(|item| unsafe { &item.value })
+    }
+    pub fn par_iter_mut(&mut self) -> impl IndexedParallelIterator<Item = &mut T>
+    where
+        T: Send,
+    {
+        // SAFETY: as in `par_iter`
+        self.data[..self.len]
+            .par_iter_mut()
+            .map(|item| unsafe { &mut item.value })
+    }
+    /// Parallel counterpart of `iter_with_keys`. Finding the keys takes a sequential
+    /// pass over the slots up to `start_of_clean` first.
+    pub fn par_iter_with_keys(&self) -> impl IndexedParallelIterator<Item = (K, &T)>
+    where
+        T: Sync,
+        K: Send + Sync,
+    {
+        self.slot_owners()
+            .into_par_iter()
+            .zip(self.par_iter())
+            .map(move |(owner, value)| (self.index_to_key(owner), value))
+    }
+    /// Removes every value for which `keep` returns false. `keep` is evaluated in
+    /// parallel; the removals themselves are sequential. Keys of kept values stay valid.
+    pub fn par_retain<F>(&mut self, keep: F)
+    where
+        T: Sync,
+        F: Fn(&T) -> bool + Sync + Send,
+    {
+        let kept: Vec<bool> = self.par_iter().map(keep).collect();
+        let doomed: Vec<usize> = self
+            .slot_owners()
+            .into_iter()
+            .zip(kept)
+            .filter(|&(_, kept)| !kept)
+            .map(|(owner, _)| owner)
+            .collect();
+        for owner in doomed {
+            self.remove_index(owner);
+        }
+        self.check_invariants();
+    }
+}
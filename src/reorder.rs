@@ -0,0 +1,126 @@
+// Rearranging the dense region without invalidating keys, including splitting it into
+// an active prefix and an inactive rest. The first rearrangement switches the storage to
+// a key table, see `tracked.rs`, which it keeps until the next `clear`, or the next
+// `assign_new_keys` that leaves no inactive values, along with the memory it takes.
+
+use crate::{ContigError, ContigStorage, Keylike};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+impl<T, K> ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// Stably sorts the dense region. Every key still resolves to the same value. The
+    /// active prefix and the inactive rest are sorted separately. O(n log n) in `len`,
+    /// plus a pass linear in the capacity the first time, switching to the key table
+    /// described under `RemovePolicy::ShiftPreserveOrder`, with its memory cost.
+    pub fn sort_by<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let mut order: Vec<usize> = (0..self.len).collect();
+        let (active, inactive) = order.split_at_mut(self.active_len);
+        active.sort_by(|&a, &b| compare(self.get_value(a), self.get_value(b)));
+        inactive.sort_by(|&a, &b| compare(self.get_value(a), self.get_value(b)));
+        self.reorder(&order);
+        self.check_invariants();
+    }
+    /// Like `sort_by`, comparing the values by `f`.
+    pub fn sort_by_key<B, F>(&mut self, mut f: F)
+    where
+        B: Ord,
+        F: FnMut(&T) -> B,
+    {
+        self.sort_by(|a, b| f(a).cmp(&f(b)))
+    }
+    /// Like `sort_by`, but equal values may end up in any order.
+    pub fn sort_unstable_by<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let mut order: Vec<usize> = (0..self.len).collect();
+        let (active, inactive) = order.split_at_mut(self.active_len);
+        active.sort_unstable_by(|&a, &b| compare(self.get_value(a), self.get_value(b)));
+        inactive.sort_unstable_by(|&a, &b| compare(self.get_value(a), self.get_value(b)));
+        self.reorder(&order);
+        self.check_invariants();
+    }
+
+    /// Exchanges the slots of the values of `a` and `b`. Both keys stay bound to their
+    /// values. O(1), plus switching to the key table the first time, as `sort_by` does.
+    /// Both values must be active or both inactive, or it fails with
+    /// `CrossesActivePartition`.
+    pub fn swap(&mut self, a: K, b: K) -> Result<(), ContigError> {
+        let a = self.resolve_key_index(self.key_to_index(a)?)?;
+        let b = self.resolve_key_index(self.key_to_index(b)?)?;
+        if (a < self.active_len) != (b < self.active_len) {
+            return Err(ContigError::CrossesActivePartition);
+        }
+        self.swap_slots(a, b);
+        self.check_invariants();
+        Ok(())
+    }
+    /// Moves the value of `key` to the start of the slice, or of the inactive rest if it
+    /// is inactive. See `move_to_index`.
+    pub fn move_to_front(&mut self, key: K) -> Result<(), ContigError> {
+        let slot = self.resolve_key_index(self.key_to_index(key)?)?;
+        let front = if slot < self.active_len { 0 } else { self.active_len };
+        self.move_to_index(key, front)
+    }
+    /// Moves the value of `key` to slot `index`, shifting the values in between by one.
+    /// O(n) in the distance moved, plus switching to the key table the first time, as
+    /// `sort_by` does.
+    /// Fails with `OutOfBounds` if `index >= len`, and with `CrossesActivePartition` if
+    /// the value is active and `index` isn't below `active_len`, or the other way round.
+    pub fn move_to_index(&mut self, key: K, index: usize) -> Result<(), ContigError> {
+        let slot = self.resolve_key_index(self.key_to_index(key)?)?;
+        if index >= self.len {
+            return Err(ContigError::OutOfBounds);
+        }
+        if (slot < self.active_len) != (index < self.active_len) {
+            return Err(ContigError::CrossesActivePartition);
+        }
+        self.move_slot(slot, index);
+        self.check_invariants();
+        Ok(())
+    }
+
+    /// Values past the active prefix of the slice keep their keys, but are left out of
+    /// `get_active_slice`. All values are active when added. Toggling is O(1), plus
+    /// switching to the key table the first time, as `sort_by` does, and reorders the
+    /// slice.
+    pub fn deactivate(&mut self, key: K) -> Result<(), ContigError> {
+        let slot = self.resolve_key_index(self.key_to_index(key)?)?;
+        if slot < self.active_len {
+            self.swap_slots(slot, self.active_len - 1);
+            self.active_len -= 1;
+        }
+        self.check_invariants();
+        Ok(())
+    }
+    pub fn activate(&mut self, key: K) -> Result<(), ContigError> {
+        let slot = self.resolve_key_index(self.key_to_index(key)?)?;
+        if slot >= self.active_len {
+            self.swap_slots(slot, self.active_len);
+            self.active_len += 1;
+        }
+        self.check_invariants();
+        Ok(())
+    }
+    pub fn is_active(&self, key: K) -> Result<bool, ContigError> {
+        Ok(self.resolve_key_index(self.key_to_index(key)?)? < self.active_len)
+    }
+    pub fn active_len(&self) -> usize {
+        self.active_len
+    }
+    /// The active prefix of `get_slice`.
+    pub fn get_active_slice(&self) -> &[T] {
+        &self.get_slice()[..self.active_len]
+    }
+    pub fn get_active_slice_mut(&mut self) -> &mut [T] {
+        let active_len = self.active_len;
+        &mut self.get_slice_mut()[..active_len]
+    }
+}
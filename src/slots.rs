@@ -0,0 +1,330 @@
+// The slot bookkeeping shared by `ContigStorage` and `ArrayContigStorage`. Both keep
+// their values in the slots below `len`, and the keys of values that were moved down to
+// fill a hole in chains of indirections past it. Everything that reads or writes those
+// chains lives here, over borrowed slots and indirect-only flags, so that the heap and
+// the inline storage can't drift apart. Key encoding, growing and the key table are left
+// to the owners.
+
+use crate::{ContigError, Corruption, Item, SlotContents, Stats};
+use alloc::vec;
+use bit_vec::BitVec;
+use core::fmt::{self, Debug};
+use core::mem::size_of;
+
+// One bit per slot: set while the slot's value is only reachable through an indirection.
+pub(crate) trait Flags {
+    fn get_flag(&self, slot: usize) -> bool;
+    fn set_flag(&mut self, slot: usize, flag: bool);
+    fn clear_flags(&mut self);
+}
+impl Flags for BitVec {
+    fn get_flag(&self, slot: usize) -> bool {
+        self.get(slot).unwrap()
+    }
+    fn set_flag(&mut self, slot: usize, flag: bool) {
+        self.set(slot, flag)
+    }
+    fn clear_flags(&mut self) {
+        self.clear()
+    }
+}
+// Inline, for storages that can't allocate.
+impl<const N: usize> Flags for [bool; N] {
+    fn get_flag(&self, slot: usize) -> bool {
+        self[slot]
+    }
+    fn set_flag(&mut self, slot: usize, flag: bool) {
+        self[slot] = flag
+    }
+    fn clear_flags(&mut self) {
+        *self = [false; N];
+    }
+}
+
+// Read access to the slots. The capacity is `data.len()`.
+pub(crate) struct Slots<'a, T: Copy, F> {
+    pub(crate) data: &'a [Item<T>],
+    pub(crate) len: usize,
+    pub(crate) start_of_clean: usize,
+    pub(crate) flags: &'a F,
+}
+// Write access to the slots, see `Slots`.
+pub(crate) struct SlotsMut<'a, T: Copy, F> {
+    pub(crate) data: &'a mut [Item<T>],
+    pub(crate) len: &'a mut usize,
+    pub(crate) start_of_clean: &'a mut usize,
+    pub(crate) flags: &'a mut F,
+}
+
+impl<'a, T, F> Slots<'a, T, F>
+where
+    T: Copy,
+    F: Flags,
+{
+    pub(crate) fn slot_contents(&self, index: usize) -> SlotContents {
+        if index < self.len {
+            return SlotContents::Data;
+        }
+        // SAFETY: slots at or past `len` never hold a value
+        if unsafe { self.data[index].is_nothing() } {
+            SlotContents::Nothing
+        } else {
+            SlotContents::Indirection
+        }
+    }
+    // where the indirection in slot `index` points. Only for slots at or past `len`.
+    pub(crate) fn indirection_target(&self, index: usize) -> usize {
+        assert!(index >= self.len);
+        // SAFETY: as in `slot_contents`
+        unsafe { self.data[index].get_indirection() }
+    }
+    // Only for slots below `len`.
+    pub(crate) fn value(&self, index: usize) -> &'a T {
+        assert!(index < self.len);
+        // SAFETY: every slot below `len` was last written through `value`
+        unsafe { &self.data[index].value }
+    }
+    // The dense region as a `[T]`, see `ContigStorage::get_slice`.
+    pub(crate) fn values(&self) -> &'a [T] {
+        assert_contiguous::<T>();
+        // SAFETY: `Item<T>` is `repr(C)` and, as checked, no bigger than `T`, so the
+        // slots are laid out exactly as a `[T]`. Slots below `len` hold values.
+        unsafe { &*(&self.data[..self.len] as *const [Item<T>] as *const [T]) }
+    }
+
+    // Resolves the index a key decoded to, to the slot holding its value. Flagged slots
+    // may only be passed through as part of a chain.
+    pub(crate) fn resolve_key_index(&self, index: usize) -> Result<usize, ContigError> {
+        if index >= self.data.len() {
+            return Err(ContigError::InvalidKey);
+        }
+        if let SlotContents::Nothing = self.slot_contents(index) {
+            return Err(ContigError::StaleKey);
+        }
+        if self.flags.get_flag(index) {
+            return Err(ContigError::IndirectOnlyAccess);
+        }
+        self.resolve_index(index).ok_or(ContigError::Corrupted)
+    }
+    // follows the chain of indirections starting at `index` to the slot holding its data
+    pub(crate) fn resolve_index(&self, mut index: usize) -> Option<usize> {
+        loop {
+            if index >= self.data.len() {
+                return None;
+            }
+            match self.slot_contents(index) {
+                SlotContents::Nothing => return None,
+                SlotContents::Indirection => index = self.indirection_target(index),
+                SlotContents::Data => return Some(index),
+            }
+        }
+    }
+
+    // The checks of `ContigStorage::validate` that concern the slots and their flags.
+    // Allocates a bit per slot.
+    pub(crate) fn validate(&self) -> Result<(), Corruption> {
+        let capacity = self.data.len();
+        if self.len > self.start_of_clean || self.start_of_clean > capacity {
+            return Err(Corruption::BoundsOutOfOrder);
+        }
+        let unclean = |&i: &usize| !matches!(self.slot_contents(i), SlotContents::Nothing);
+        if let Some(i) = (self.start_of_clean..capacity).find(unclean) {
+            return Err(Corruption::UncleanSlot(i));
+        }
+        let mut pointed_to = BitVec::from_elem(capacity, false);
+        for i in self.len..self.start_of_clean {
+            if let SlotContents::Indirection = self.slot_contents(i) {
+                let target = self.indirection_target(i);
+                if target >= i {
+                    return Err(Corruption::ForwardIndirection(i));
+                }
+                if pointed_to.get(target).unwrap() {
+                    return Err(Corruption::SharedTarget(target));
+                }
+                pointed_to.set(target, true);
+                // the target is lower, so it has been checked to point down already
+                if let SlotContents::Nothing = self.slot_contents(target) {
+                    return Err(Corruption::DanglingIndirection(i));
+                }
+            }
+        }
+        match (0..capacity).find(|&i| pointed_to.get(i).unwrap() != self.flags.get_flag(i)) {
+            Some(i) => Err(Corruption::IndirectOnlyMismatch(i)),
+            None => Ok(()),
+        }
+    }
+    // Measures the chains, in time linear in `start_of_clean`. The owner fills in
+    // `grow_count` and `key_table`. Allocates a word per slot past `len`.
+    pub(crate) fn stats(&self) -> Stats {
+        // Targets lie below their indirections, so chain lengths fill in from the bottom.
+        let mut chain_lengths = vec![0; self.start_of_clean - self.len];
+        for i in self.len..self.start_of_clean {
+            if let SlotContents::Indirection = self.slot_contents(i) {
+                let target = self.indirection_target(i);
+                let below = if target < self.len { 0 } else { chain_lengths[target - self.len] };
+                chain_lengths[i - self.len] = below + 1;
+            }
+        }
+        Stats {
+            len: self.len,
+            capacity: self.data.len(),
+            start_of_clean: self.start_of_clean,
+            indirections: chain_lengths.iter().filter(|&&l| l > 0).count(),
+            indirect_only: (0..self.len).filter(|&i| self.flags.get_flag(i)).count(),
+            max_chain_length: chain_lengths.iter().copied().max().unwrap_or(0),
+            grow_count: 0,
+            key_table: false,
+        }
+    }
+}
+
+// The slot layout, marking flagged slots with `@` and the boundary with `|`. The owner
+// finishes the last line.
+impl<'a, T, F> Debug for Slots<'a, T, F>
+where
+    T: Copy + Debug,
+    F: Flags,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        for i in 0..self.data.len() {
+            let x = if self.flags.get_flag(i) { "@" } else { " " };
+            f.write_fmt(format_args!("{: >3}{}", i, x))?
+        }
+        f.write_str("\n[")?;
+        for i in 0..self.data.len() {
+            if i > 0 {
+                f.write_str(if i == self.len { "|" } else { "," })?;
+            }
+            match self.slot_contents(i) {
+                SlotContents::Data => self.value(i).fmt(f)?,
+                SlotContents::Indirection => {
+                    f.write_fmt(format_args!("@{: <2}", self.indirection_target(i)))?
+                }
+                SlotContents::Nothing => f.write_str(" _ ")?,
+            }
+        }
+        f.write_fmt(format_args!("] len: {}", self.len))
+    }
+}
+
+impl<'a, T, F> SlotsMut<'a, T, F>
+where
+    T: Copy,
+    F: Flags,
+{
+    pub(crate) fn as_slots(&self) -> Slots<'_, T, F> {
+        Slots {
+            data: self.data,
+            len: *self.len,
+            start_of_clean: *self.start_of_clean,
+            flags: self.flags,
+        }
+    }
+    // Only for slots below `len`.
+    pub(crate) fn into_value_mut(self, index: usize) -> &'a mut T {
+        assert!(index < *self.len);
+        // SAFETY: as in `Slots::value`
+        unsafe { &mut self.data[index].value }
+    }
+    // See `Slots::values`. Writing through the `[T]` keeps the slots values.
+    pub(crate) fn into_values_mut(self) -> &'a mut [T] {
+        assert_contiguous::<T>();
+        // SAFETY: as in `Slots::values`
+        unsafe { &mut *(&mut self.data[..*self.len] as *mut [Item<T>] as *mut [T]) }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for x in self.data[0..*self.start_of_clean].iter_mut() {
+            *x = Item::<T>::NOTHING_ITEM;
+        }
+        *self.len = 0;
+        *self.start_of_clean = 0;
+        self.flags.clear_flags();
+    }
+
+    // Adds `value` at the boundary, returning the index its key encodes. The caller
+    // makes sure that `len` is below the capacity.
+    pub(crate) fn add(&mut self, value: T) -> Result<usize, ContigError> {
+        let boundary = *self.len;
+        *self.start_of_clean = (*self.start_of_clean).max(boundary + 1);
+        match self.as_slots().slot_contents(boundary) {
+            SlotContents::Nothing => {
+                self.data[boundary].value = value;
+                *self.len += 1;
+                Ok(boundary)
+            }
+            SlotContents::Indirection => {
+                let real_location = self.as_slots().indirection_target(boundary);
+                // Bring the data reached by the indirection back to boundary. Boundary keeps
+                // its bit: if it was itself reached through an indirection, it still is.
+                self.data[boundary].value = *self.as_slots().value(real_location);
+                // occupy the data previously reached by the indirection
+                self.data[real_location].value = value;
+                self.flags.set_flag(real_location, false);
+                *self.len += 1;
+                Ok(real_location)
+            }
+            SlotContents::Data => {
+                // there should NOT be data beyond the boundary!
+                Err(ContigError::Corrupted)
+            }
+        }
+    }
+
+    // Removes the value the key index `index` resolves to, unlinking its chain.
+    pub(crate) fn remove_index(&mut self, mut index: usize) -> Option<T> {
+        if index >= self.data.len() {
+            return None;
+        }
+        if let SlotContents::Data = self.as_slots().slot_contents(index) {
+            if self.flags.get_flag(index) {
+                // no direct access allowed >=[
+                return None;
+            }
+        }
+        // unlink the chain, so that the slot holding the value is directly accessible
+        loop {
+            match self.as_slots().slot_contents(index) {
+                SlotContents::Nothing => return None,
+                SlotContents::Indirection => {
+                    let real_location = self.as_slots().indirection_target(index);
+                    self.data[index].set_nothing();
+                    self.flags.set_flag(real_location, false);
+                    index = real_location;
+                }
+                SlotContents::Data => break,
+            }
+        }
+        let value = *self.as_slots().value(index);
+        self.fill_hole(index);
+        Some(value)
+    }
+
+    // invoked when index < len and index now logically contains Nothing
+    fn fill_hole(&mut self, index: usize) {
+        let boundary = *self.len - 1;
+        if boundary == index {
+            // removed the boundary!
+            self.data[index].set_nothing();
+            self.flags.set_flag(index, false);
+        } else {
+            // boundary now contains a data lement that is LEFT of len
+            // must move boundary into my slot and put indirection there
+            self.data[index].value = *self.as_slots().value(boundary);
+            self.flags.set_flag(index, true);
+            self.data[boundary].set_indirection(index);
+        }
+        *self.len -= 1;
+    }
+}
+
+// Slots are wider than a `T` smaller than a `usize`, and aren't laid out as a `[T]`.
+fn assert_contiguous<T: Copy>() {
+    if size_of::<Item<T>>() > size_of::<T>() {
+        panic!(
+            "Cannot store contiguously! Size of type ({} bytes) < size of usize ({})",
+            size_of::<T>(),
+            size_of::<Item<T>>()
+        );
+    }
+}
@@ -0,0 +1,175 @@
+// A `ContigStorage` whose dense region stays ordered by a sort key through every `add`,
+// `remove` and `update`, so that ranges of sort keys map to ranges of the slice. Values
+// are moved into place with `RemovePolicy::ShiftPreserveOrder` and the key table, which
+// makes each of these O(n) in the worst case, but never O(n log n) as re-sorting would.
+// Among values with equal sort keys, `add` puts the new one last, and `update` moves a
+// value no further than its new sort key requires.
+
+use crate::{ContigError, ContigStorage, GrowBehavior, Iter, Key, Keylike, RemovePolicy};
+use core::ops::{Bound, Range, RangeBounds};
+
+/// What a `SortedContigStorage` orders its values by. Implemented for every
+/// `Fn(&T) -> B` with `B: Ord`.
+pub trait SortKey<T> {
+    type Output: Ord;
+    fn sort_key(&self, value: &T) -> Self::Output;
+}
+impl<T, B, F> SortKey<T> for F
+where
+    B: Ord,
+    F: Fn(&T) -> B,
+{
+    type Output = B;
+    fn sort_key(&self, value: &T) -> B {
+        self(value)
+    }
+}
+
+pub struct SortedContigStorage<T: Copy, F, K: Keylike = Key> {
+    storage: ContigStorage<T, K>,
+    sort_key: F,
+    // The policy `storage` came with, handed back by `into_inner`.
+    remove_policy: RemovePolicy,
+}
+
+impl<T, F> SortedContigStorage<T, F>
+where
+    T: Copy,
+    F: SortKey<T>,
+{
+    pub fn new(capacity: usize, grow_behavior: GrowBehavior, sort_key: F) -> Self {
+        Self::from_storage(ContigStorage::new(capacity, grow_behavior), sort_key)
+    }
+}
+impl<T, F, K> SortedContigStorage<T, F, K>
+where
+    T: Copy,
+    F: SortKey<T>,
+    K: Keylike,
+{
+    /// Sorts `storage` and takes it over. Its keys remain valid, and all its values
+    /// become active. Its `remove_policy` is `ShiftPreserveOrder` until `into_inner`.
+    pub fn from_storage(mut storage: ContigStorage<T, K>, sort_key: F) -> Self {
+        let remove_policy =
+            core::mem::replace(&mut storage.remove_policy, RemovePolicy::ShiftPreserveOrder);
+        storage.active_len = storage.len();
+        storage.sort_by(|a, b| sort_key.sort_key(a).cmp(&sort_key.sort_key(b)));
+        Self { storage, sort_key, remove_policy }
+    }
+    /// Read access to the underlying storage.
+    pub fn storage(&self) -> &ContigStorage<T, K> {
+        &self.storage
+    }
+    /// Gives back the storage with the `remove_policy` it was created with.
+    pub fn into_inner(mut self) -> ContigStorage<T, K> {
+        self.storage.remove_policy = self.remove_policy;
+        self.storage
+    }
+
+    // The first slot in `range` for which `pred` is false, given that it holds for a
+    // prefix of the range.
+    fn partition_point<P>(&self, range: Range<usize>, mut pred: P) -> usize
+    where
+        P: FnMut(&F::Output) -> bool,
+    {
+        let (mut low, mut high) = (range.start, range.end);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if pred(&self.sort_key.sort_key(self.storage.get_value(mid))) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+    // Moves the value in `slot`, which may be out of order, to where it belongs.
+    fn settle(&mut self, slot: usize) {
+        let sort_key = self.sort_key.sort_key(self.storage.get_value(slot));
+        let mut to = self.partition_point(0..slot, |k| *k <= sort_key);
+        if to == slot {
+            // once taken out, the values after it move down by one
+            to = self.partition_point(slot + 1..self.storage.len(), |k| *k < sort_key) - 1;
+        }
+        if to != slot {
+            self.storage.move_slot(slot, to);
+        }
+    }
+
+    /// Inserts `value` after all values with a lesser or equal sort key.
+    pub fn add(&mut self, value: T) -> Result<K, ContigError> {
+        let key = self.storage.add(value)?;
+        self.settle(self.storage.len() - 1);
+        self.storage.check_invariants();
+        Ok(key)
+    }
+    pub fn remove(&mut self, key: K) -> Option<T> {
+        self.storage.remove(key)
+    }
+    pub fn try_remove(&mut self, key: K) -> Result<T, ContigError> {
+        self.storage.try_remove(key)
+    }
+    pub fn get(&self, key: K) -> Option<&T> {
+        self.storage.get(key)
+    }
+    pub fn try_get(&self, key: K) -> Result<&T, ContigError> {
+        self.storage.try_get(key)
+    }
+    /// Modifies the value of `key` with `f`, then moves it to where its new sort key
+    /// belongs. Returns the value as updated.
+    pub fn update<G>(&mut self, key: K, f: G) -> Option<&T>
+    where
+        G: FnOnce(&mut T),
+    {
+        self.try_update(key, f).ok()
+    }
+    pub fn try_update<G>(&mut self, key: K, f: G) -> Result<&T, ContigError>
+    where
+        G: FnOnce(&mut T),
+    {
+        let slot = self.storage.resolve_key_index(self.storage.key_to_index(key)?)?;
+        f(self.storage.get_mut_value(slot));
+        self.settle(slot);
+        self.storage.check_invariants();
+        self.storage.try_get(key)
+    }
+
+    /// The slots of the values whose sort key lies in `range`, found by binary search.
+    pub fn range<R>(&self, range: R) -> Range<usize>
+    where
+        R: RangeBounds<F::Output>,
+    {
+        let len = self.storage.len();
+        let start = match range.start_bound() {
+            Bound::Included(b) => self.partition_point(0..len, |k| k < b),
+            Bound::Excluded(b) => self.partition_point(0..len, |k| k <= b),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(b) => self.partition_point(start..len, |k| k <= b),
+            Bound::Excluded(b) => self.partition_point(start..len, |k| k < b),
+            Bound::Unbounded => len,
+        };
+        start..end
+    }
+    /// The values ordered by their sort key. Panics if `T` is smaller than a `usize`,
+    /// as `ContigStorage::get_slice` does.
+    pub fn get_slice(&self) -> &[T] {
+        self.storage.get_slice()
+    }
+    pub fn get_slice_index(&self, key: K) -> Option<usize> {
+        self.storage.get_slice_index(key)
+    }
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.storage.iter()
+    }
+    pub fn clear(&mut self) {
+        self.storage.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+}
@@ -0,0 +1,51 @@
+// Synchronisation primitives used by `ConcurrentContigStorage`. Under `--cfg loom` these
+// are loom's model-checked versions; otherwise thin std wrappers with the same API.
+
+#[cfg(loom)]
+pub(crate) use loom::{
+    cell::{ConstPtr, UnsafeCell},
+    sync::{
+        atomic::{fence, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread::yield_now,
+};
+
+#[cfg(not(loom))]
+pub(crate) use std::{
+    sync::{
+        atomic::{fence, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread::yield_now,
+};
+
+#[cfg(not(loom))]
+pub(crate) struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    pub(crate) fn new(data: T) -> Self {
+        UnsafeCell(std::cell::UnsafeCell::new(data))
+    }
+    pub(crate) fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+    pub(crate) fn get(&self) -> ConstPtr<T> {
+        ConstPtr(self.0.get())
+    }
+    pub(crate) fn with_mut<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(*mut T) -> R,
+    {
+        f(self.0.get())
+    }
+}
+
+#[cfg(not(loom))]
+pub(crate) struct ConstPtr<T>(*const T);
+#[cfg(not(loom))]
+impl<T> ConstPtr<T> {
+    pub(crate) unsafe fn deref(&self) -> &T {
+        &*self.0
+    }
+}
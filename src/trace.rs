@@ -0,0 +1,45 @@
+// Spans around the expensive calls, for the `tracing` feature. Without it, `Span` is
+// empty and nothing is recorded.
+
+#[cfg(feature = "tracing")]
+pub(crate) struct Span {
+    _entered: tracing::span::EnteredSpan,
+    start: std::time::Instant,
+}
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+#[cfg(feature = "tracing")]
+impl Drop for Span {
+    fn drop(&mut self) {
+        tracing::debug!(elapsed = ?self.start.elapsed(), "done");
+    }
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($name:literal, $($field:ident),*) => {
+        Span {
+            _entered: tracing::debug_span!($name, $($field),*).entered(),
+            start: std::time::Instant::now(),
+        }
+    };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($name:literal, $($field:ident),*) => {{
+        $(let _ = $field;)*
+        Span
+    }};
+}
+
+pub(crate) fn grow(old_capacity: usize, new_capacity: usize) -> Span {
+    span!("grow", old_capacity, new_capacity)
+}
+pub(crate) fn clear(len: usize, capacity: usize) -> Span {
+    span!("clear", len, capacity)
+}
+// `start_of_clean` is what a rekey brings down to `len`
+pub(crate) fn rekey(len: usize, start_of_clean: usize) -> Span {
+    span!("rekey", len, start_of_clean)
+}
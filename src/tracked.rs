@@ -0,0 +1,187 @@
+// An explicit mapping between key indices and slots, for storages whose values were moved
+// in ways the indirection chains can't express: a key index below `len` always names its
+// own slot, so once another value has to sit there, that key can't be redirected. A
+// storage switches to the mapping on the first such move, and back to chains on `clear`
+// and `assign_new_keys`, which issue fresh keys anyway. While tracking, all slots past
+// `len` are NOTHING and `start_of_clean == len`.
+
+use crate::{ContigStorage, Corruption, Item, Keylike};
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub(crate) const UNUSED: usize = usize::MAX;
+
+#[derive(Clone)]
+pub(crate) struct Tracked {
+    // per key index below the capacity: the slot holding its value, or UNUSED
+    pub(crate) slot_of: Vec<usize>,
+    // per slot below `len`: the key index owning it
+    pub(crate) key_of: Vec<usize>,
+    // key indices not handed out, least recently freed first, which puts off their reuse
+    pub(crate) free: VecDeque<usize>,
+}
+
+impl Tracked {
+    pub(crate) fn grow(&mut self, new_capacity: usize) {
+        let old_capacity = self.slot_of.len();
+        self.slot_of.resize(new_capacity, UNUSED);
+        self.free.extend(old_capacity..new_capacity);
+    }
+}
+
+impl<T, K> ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// Switches to an explicit key table, keeping every key valid. Linear in the capacity.
+    pub(crate) fn track(&mut self) {
+        if self.tracked.is_some() {
+            return;
+        }
+        let key_of = self.slot_owners();
+        let mut slot_of = vec![UNUSED; self.capacity()];
+        for (slot, &index) in key_of.iter().enumerate() {
+            slot_of[index] = slot;
+        }
+        let free = (0..self.capacity()).filter(|&i| slot_of[i] == UNUSED).collect();
+        // the chains are now redundant
+        for x in self.data[self.len..self.start_of_clean].iter_mut() {
+            *x = Item::<T>::NOTHING_ITEM;
+        }
+        self.start_of_clean = self.len;
+        self.indirect_only_bitfield.clear();
+        self.tracked = Some(Tracked {
+            slot_of,
+            key_of,
+            free,
+        });
+    }
+
+    // Appends `value` to the active values, returning its key index. There must be room.
+    pub(crate) fn tracked_add(&mut self, value: T) -> usize {
+        let slot = self.len;
+        let tracked = self.tracked.as_mut().unwrap();
+        let index = tracked.free.pop_front().expect("no free key index below the capacity");
+        tracked.slot_of[index] = slot;
+        tracked.key_of.push(index);
+        self.data[slot].value = value;
+        self.len += 1;
+        self.start_of_clean = self.len;
+        if self.active_len < slot {
+            self.swap_slots(slot, self.active_len);
+        }
+        self.active_len += 1;
+        index
+    }
+
+    // Removes the value of key index `index`, moving later values down by one if
+    // `shift`, or the last value into its slot otherwise.
+    pub(crate) fn tracked_remove(&mut self, index: usize, shift: bool) -> Option<T> {
+        let mut slot = *self.tracked.as_ref()?.slot_of.get(index)?;
+        if slot == UNUSED {
+            return None;
+        }
+        let value = self.copy_value(slot);
+        let last = self.len - 1;
+        if slot < self.active_len {
+            self.active_len -= 1;
+            if !shift && self.active_len < last {
+                // keep the active values in front: take the hole to the end through the
+                // last active slot
+                self.swap_slots(slot, self.active_len);
+                self.swap_slots(self.active_len, last);
+                slot = last;
+            }
+        }
+        let tracked = self.tracked.as_mut().unwrap();
+        if shift {
+            self.data.copy_within(slot + 1..=last, slot);
+            tracked.key_of.remove(slot);
+            for (moved, &owner) in tracked.key_of.iter().enumerate().skip(slot) {
+                tracked.slot_of[owner] = moved;
+            }
+        } else {
+            self.data[slot] = self.data[last];
+            tracked.key_of.swap_remove(slot);
+            if slot < last {
+                tracked.slot_of[tracked.key_of[slot]] = slot;
+            }
+        }
+        tracked.slot_of[index] = UNUSED;
+        tracked.free.push_back(index);
+        self.data[last].set_nothing();
+        self.len -= 1;
+        self.start_of_clean = self.len;
+        Some(value)
+    }
+
+    // Rearranges the dense region so that slot `i` holds what slot `order[i]` held, and
+    // every key follows its value. `order` must be a permutation of `0..len`.
+    pub(crate) fn reorder(&mut self, order: &[usize]) {
+        self.track();
+        let items: Vec<Item<T>> = order.iter().map(|&slot| self.data[slot]).collect();
+        self.data[..self.len].copy_from_slice(&items);
+        let tracked = self.tracked.as_mut().unwrap();
+        tracked.key_of = order.iter().map(|&slot| tracked.key_of[slot]).collect();
+        for (slot, &index) in tracked.key_of.iter().enumerate() {
+            tracked.slot_of[index] = slot;
+        }
+    }
+
+    // Moves the value in slot `from` to slot `to`, shifting the values in between by one
+    // towards `from`. Keys follow their values.
+    pub(crate) fn move_slot(&mut self, from: usize, to: usize) {
+        self.track();
+        let tracked = self.tracked.as_mut().unwrap();
+        let (low, high) = (from.min(to), from.max(to));
+        if from < to {
+            self.data[low..=high].rotate_left(1);
+            tracked.key_of[low..=high].rotate_left(1);
+        } else {
+            self.data[low..=high].rotate_right(1);
+            tracked.key_of[low..=high].rotate_right(1);
+        }
+        for slot in low..=high {
+            let index = tracked.key_of[slot];
+            tracked.slot_of[index] = slot;
+        }
+    }
+
+    // Exchanges the values in slots `a` and `b`. Keys follow their values.
+    pub(crate) fn swap_slots(&mut self, a: usize, b: usize) {
+        self.track();
+        let tracked = self.tracked.as_mut().unwrap();
+        self.data.swap(a, b);
+        tracked.key_of.swap(a, b);
+        tracked.slot_of[tracked.key_of[a]] = a;
+        tracked.slot_of[tracked.key_of[b]] = b;
+    }
+
+    pub(crate) fn validate_tracked(&self, tracked: &Tracked) -> Result<(), Corruption> {
+        if self.start_of_clean != self.len {
+            return Err(Corruption::BoundsOutOfOrder);
+        }
+        if tracked.slot_of.len() != self.capacity()
+            || tracked.key_of.len() != self.len
+            || tracked.free.len() != self.capacity() - self.len
+        {
+            return Err(Corruption::CapacityMismatch);
+        }
+        for (slot, &index) in tracked.key_of.iter().enumerate() {
+            if tracked.slot_of.get(index) != Some(&slot) {
+                return Err(Corruption::KeyTableMismatch(slot));
+            }
+        }
+        // with the above, every other key index must be free, exactly once
+        let mut seen = vec![false; self.capacity()];
+        for &index in tracked.free.iter() {
+            if tracked.slot_of.get(index) != Some(&UNUSED) || seen[index] {
+                return Err(Corruption::KeyTableMismatch(index));
+            }
+            seen[index] = true;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+// Undoable batches of operations. Before each operation, a `Transaction` records the slots
+// it may write, along with their indirect-only bits, and the generation a remove bumps, so
+// rolling back only restores those and the scalar bookkeeping. A slot may be recorded
+// several times; replaying the log backwards leaves each with its oldest recorded
+// contents. Slots past the capacity at the start were added by growing, and are cut off
+// again.
+//
+// With a key table, recording a slot also records which key index owned it, which undoes
+// the table in the same way. Key indices are handed out from the front of the free queue
+// and returned to its back, so it is restored by cutting off what was returned and
+// putting back what was handed out.
+
+use crate::tracked::UNUSED;
+use crate::{ContigError, ContigStorage, Item, Key, Keylike, RemovePolicy, SlotContents};
+use alloc::vec::Vec;
+
+/// Guards a `ContigStorage` during a transaction, see `ContigStorage::begin_transaction`.
+/// Dropping it without calling `commit` rolls the storage back.
+pub struct Transaction<'a, T, K = Key>
+where
+    T: Copy,
+    K: Keylike,
+{
+    storage: &'a mut ContigStorage<T, K>,
+    // None once committed
+    snapshot: Option<Snapshot<T>>,
+}
+
+struct Snapshot<T: Copy> {
+    log: Vec<(usize, Item<T>, bool)>,
+    capacity: usize,
+    len: usize,
+    active_len: usize,
+    start_of_clean: usize,
+    indirection_xor: usize,
+    key_secret: [u64; 2],
+    grow_count: usize,
+    // (key index, generation), recorded before a remove bumps it
+    generations: Vec<(usize, u16)>,
+    // None if there was no key table, which rolling back drops again
+    key_table: Option<KeyTableLog>,
+}
+
+struct KeyTableLog {
+    // (slot, key index owning it), recorded along with the slot
+    owners: Vec<(usize, usize)>,
+    free_len: usize,
+    // key indices taken from the front of the free queue, in order
+    taken: Vec<usize>,
+}
+
+impl<T, K> ContigStorage<T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    /// Starts a transaction: until it is committed, everything done through it is undone
+    /// when it is dropped, and keys issued before behave as if nothing happened. Rolling
+    /// back takes time proportional to the slots touched, also with a key table.
+    pub fn begin_transaction(&mut self) -> Transaction<'_, T, K> {
+        let key_table = self.tracked.as_ref().map(|tracked| KeyTableLog {
+            owners: Vec::new(),
+            free_len: tracked.free.len(),
+            taken: Vec::new(),
+        });
+        let snapshot = Snapshot {
+            log: Vec::new(),
+            capacity: self.capacity(),
+            len: self.len,
+            active_len: self.active_len,
+            start_of_clean: self.start_of_clean,
+            indirection_xor: self.indirection_xor,
+            key_secret: self.key_secret,
+            grow_count: self.grow_count,
+            generations: Vec::new(),
+            key_table,
+        };
+        Transaction {
+            storage: self,
+            snapshot: Some(snapshot),
+        }
+    }
+}
+
+impl<'a, T, K> Transaction<'a, T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    fn record(&mut self, slot: usize) {
+        let storage = &*self.storage;
+        let snapshot = self.snapshot.as_mut().unwrap();
+        if slot < storage.capacity() {
+            let bit = storage.indirect_only_bitfield.get(slot).unwrap();
+            snapshot.log.push((slot, storage.data[slot], bit));
+        }
+        if let (Some(log), Some(tracked)) = (&mut snapshot.key_table, &storage.tracked) {
+            if let Some(&index) = tracked.key_of.get(slot) {
+                log.owners.push((slot, index));
+            }
+        }
+    }
+    // Records what switching to the key table overwrites, if the next `add` or `remove`
+    // will: the chains, and the bits of their targets.
+    fn record_tracking(&mut self) {
+        let storage = &*self.storage;
+        let will_track = storage.remove_policy == RemovePolicy::ShiftPreserveOrder
+            || storage.active_len < storage.len;
+        if storage.tracked.is_some() || !will_track {
+            return;
+        }
+        for slot in storage.len..storage.start_of_clean {
+            if let SlotContents::Indirection = self.storage.slot_contents(slot) {
+                let target = self.storage.indirection_target(slot);
+                self.record(slot);
+                self.record(target);
+            }
+        }
+    }
+
+    pub fn add(&mut self, value: T) -> Result<K, ContigError> {
+        self.record_tracking();
+        let boundary = self.storage.len;
+        self.record(boundary);
+        self.record(self.storage.active_len);
+        if boundary < self.storage.capacity() {
+            if let SlotContents::Indirection = self.storage.slot_contents(boundary) {
+                let target = self.storage.indirection_target(boundary);
+                self.record(target);
+            }
+        }
+        let snapshot = self.snapshot.as_mut().unwrap();
+        if let (Some(log), Some(tracked)) = (&mut snapshot.key_table, &self.storage.tracked) {
+            // if the queue is empty, growing refills it past the old capacity
+            log.taken.extend(tracked.free.front());
+        }
+        self.storage.add(value)
+    }
+    pub fn remove(&mut self, key: K) -> Option<T> {
+        self.try_remove(key).ok()
+    }
+    pub fn try_remove(&mut self, key: K) -> Result<T, ContigError> {
+        let mut index = self.storage.key_to_index(key)?;
+        let slot = self.storage.resolve_key_index(index)?;
+        if let Some(&generation) = self.storage.generations.get(index) {
+            self.snapshot.as_mut().unwrap().generations.push((index, generation));
+        }
+        self.record_tracking();
+        let len = self.storage.len;
+        if self.storage.tracked.is_some() || self.storage.active_len < len {
+            // the partition swaps of `tracked_remove`
+            self.record(slot);
+            self.record(self.storage.active_len.saturating_sub(1));
+        } else {
+            // the chain, unlinked on the way to the value
+            while let SlotContents::Indirection = self.storage.slot_contents(index) {
+                self.record(index);
+                index = self.storage.indirection_target(index);
+            }
+            self.record(index);
+        }
+        if self.storage.remove_policy == RemovePolicy::ShiftPreserveOrder {
+            for moved in slot..len {
+                self.record(moved);
+            }
+        }
+        self.record(len - 1);
+        self.storage.try_remove(key)
+    }
+    pub fn get_mut(&mut self, key: K) -> Option<&mut T> {
+        self.try_get_mut(key).ok()
+    }
+    pub fn try_get_mut(&mut self, key: K) -> Result<&mut T, ContigError> {
+        let slot = self.storage.resolve_key_index(self.storage.key_to_index(key)?)?;
+        self.record(slot);
+        Ok(self.storage.get_mut_value(slot))
+    }
+
+    pub fn get(&self, key: K) -> Option<&T> {
+        self.storage.get(key)
+    }
+    pub fn try_get(&self, key: K) -> Result<&T, ContigError> {
+        self.storage.try_get(key)
+    }
+    /// Read access to the storage, including the changes made so far.
+    pub fn storage(&self) -> &ContigStorage<T, K> {
+        self.storage
+    }
+
+    /// Keeps the changes.
+    pub fn commit(mut self) {
+        self.snapshot = None;
+    }
+    /// Undoes the changes, as dropping the transaction does.
+    pub fn rollback(self) {}
+}
+
+impl<'a, T, K> Drop for Transaction<'a, T, K>
+where
+    T: Copy,
+    K: Keylike,
+{
+    fn drop(&mut self) {
+        let snapshot = match self.snapshot.take() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        let storage = &mut *self.storage;
+        storage.data.truncate(snapshot.capacity);
+        storage.indirect_only_bitfield.truncate(snapshot.capacity);
+        storage.generations.truncate(snapshot.capacity);
+        for &(index, generation) in snapshot.generations.iter().rev() {
+            if index < snapshot.capacity {
+                storage.generations[index] = generation;
+            }
+        }
+        for &(slot, item, bit) in snapshot.log.iter().rev() {
+            if slot < snapshot.capacity {
+                storage.data[slot] = item;
+                storage.indirect_only_bitfield.set(slot, bit);
+            }
+        }
+        storage.len = snapshot.len;
+        storage.active_len = snapshot.active_len;
+        storage.start_of_clean = snapshot.start_of_clean;
+        storage.indirection_xor = snapshot.indirection_xor;
+        storage.key_secret = snapshot.key_secret;
+        storage.grow_count = snapshot.grow_count;
+        match (snapshot.key_table, &mut storage.tracked) {
+            (Some(log), Some(tracked)) => {
+                tracked.slot_of.truncate(snapshot.capacity);
+                tracked.key_of.resize(snapshot.len, UNUSED);
+                for &(slot, index) in log.owners.iter().rev() {
+                    // Entries for added slots, or for key indices past the old capacity,
+                    // were made during the transaction. The older entries replayed after
+                    // them restore the rest.
+                    if slot < snapshot.len && index < snapshot.capacity {
+                        tracked.key_of[slot] = index;
+                        tracked.slot_of[index] = slot;
+                    }
+                }
+                // the queue as it was is the indices taken, then what is left of it
+                let taken = &log.taken[..log.taken.len().min(log.free_len)];
+                tracked.free.truncate(log.free_len - taken.len());
+                for &index in taken.iter().rev() {
+                    tracked.slot_of[index] = UNUSED;
+                    tracked.free.push_front(index);
+                }
+            }
+            _ => storage.tracked = None,
+        }
+        storage.check_invariants();
+    }
+}